@@ -13,6 +13,7 @@ extern crate bytes;
 extern crate core_affinity;
 extern crate crossbeam;
 extern crate crossbeam_channel;
+extern crate ctrlc;
 extern crate h2;
 extern crate num_cpus;
 extern crate rustls;
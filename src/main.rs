@@ -2,6 +2,7 @@ extern crate log;
 extern crate structopt;
 
 use env_logger::{Builder, Target};
+use lib::runner::control::Control;
 use lib::runner::Runner;
 use lib::utils::Argument;
 use log::LevelFilter;
@@ -23,7 +24,16 @@ fn main() {
     });
     builder.init();
     match Runner::new(arg) {
-        Ok(mut v) => v.run(),
+        Ok(mut v) => {
+            if let Err(e) = Control::stop_on_signal(v.control()) {
+                println!("{}", e);
+            }
+            v.run();
+            // the run stopped by the abort thresholds
+            if v.control().abort_reason().is_some() {
+                std::process::exit(3);
+            }
+        }
         Err(e) => {
             println!("start runner error: {:?}", e);
         }
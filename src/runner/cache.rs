@@ -1,27 +1,99 @@
-use crate::utils::{Argument, Protocol};
+use crate::runner::expect::{Expectation, Expectations};
+use crate::runner::update::UpdateTemplate;
+use crate::utils::wire::{
+    add_tcp_length, pad_message, parse_raw_message, question_type, DEFAULT_PADDING_BLOCK,
+};
+use crate::utils::{Argument, EdnsOpt, Mode, Protocol};
 use rand::Rng;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 use std::str::FromStr;
 use trust_dns_client::proto::{
-    op::{Edns, Message, Query},
-    {rr::Name, rr::RecordType},
+    op::{Edns, Message, OpCode, Query},
+    rr::rdata::opt::{EdnsCode, EdnsOption},
+    rr::rdata::SOA,
+    {rr::Name, rr::RData, rr::Record, rr::RecordType},
 };
 
+/// check whether the edns padding can be sent over the protocol, the padding
+/// is only for encrypted transport (RFC 8467) and the dot and doh workers
+/// are not implemented yet.
+pub fn check_padding(protocol: &Protocol) -> Result<(), String> {
+    match protocol {
+        Protocol::DOT | Protocol::DOH => {
+            Err("edns padding needs dot or doh which are not supported yet".to_string())
+        }
+        Protocol::UDP | Protocol::TCP => {
+            Err("edns padding is only sent over dot or doh".to_string())
+        }
+    }
+}
+
+/// EdnsOptions is the edns options for building the query packet, which set
+/// by the arguments and can be extended by the options in query file.
+#[derive(Debug, Clone, Default)]
+pub struct EdnsOptions {
+    nsid: bool,
+    keepalive: bool,
+    padding: usize,
+    options: Vec<EdnsOpt>,
+    protocol: Protocol,
+}
+
+impl EdnsOptions {
+    pub fn new(args: &Argument) -> EdnsOptions {
+        EdnsOptions {
+            nsid: args.nsid,
+            keepalive: args.tcp_keepalive,
+            padding: args.edns_padding,
+            options: args.edns_option.clone(),
+            protocol: args.protocol.clone(),
+        }
+    }
+
+    /// update the options from the query file, the supported options are
+    /// "nsid", "keepalive", "padding" and "option=<code:hex>"
+    pub fn update(&mut self, option: &str) -> Result<(), String> {
+        match option {
+            "nsid" => self.nsid = true,
+            "keepalive" => self.keepalive = true,
+            "padding" => {
+                check_padding(&self.protocol)?;
+                if self.padding == 0 {
+                    self.padding = DEFAULT_PADDING_BLOCK;
+                }
+            }
+            _ if option.starts_with("option=") => {
+                self.options
+                    .push(EdnsOpt::from_str(&option["option=".len()..])?);
+            }
+            _ => return Err(format!("unknown query option {}", option)),
+        }
+        Ok(())
+    }
+}
+
+/// Cache keeps the prebuilt packets, the message id is allocated by the
+/// session of each socket unless it is fixed by argument.
 pub struct Cache {
-    need_rebuild: bool,
     cache: Vec<(Vec<u8>, u16)>,
     counter: usize,
     size: usize,
-    offset: usize,
+    parse_full_response: bool,
+    expectations: Expectations,
 }
 
 #[warn(dead_code)]
 impl Cache {
-    pub fn new_from_file(args: &Argument) -> Vec<(Vec<u8>, u16)> {
+    /// read the query from file, each line is "domain [type] [options...]",
+    /// the options are edns options or the expectation of response, returns
+    /// the packets, whether any line set the edns options and the expectations.
+    pub fn new_from_file(args: &Argument) -> (Vec<(Vec<u8>, u16)>, bool, Expectations) {
         let file = args.file.to_owned();
         let mut query_data = vec![];
+        let mut has_options = false;
+        let mut expectations = Expectations::new();
         if let Ok(lines) = read_lines(file) {
             for line in lines {
                 if let Ok(query_type) = line {
@@ -36,8 +108,43 @@ impl Cache {
                             continue;
                         }
                     };
-                    let qty = qtype.parse().unwrap();
-                    match Cache::build_packet(domain.to_string(), qty, args) {
+                    let qty: RecordType = match qtype.parse() {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("read query file fail: type {} not valid: {}", qtype, e);
+                            continue;
+                        }
+                    };
+                    let mut options = EdnsOptions::new(args);
+                    let mut expectation = None;
+                    let mut valid = true;
+                    for option in splitter {
+                        let result = if option.starts_with("expect") {
+                            Expectation::from_str(option).map(|v| expectation = Some(v))
+                        } else {
+                            has_options = true;
+                            options.update(option)
+                        };
+                        if let Err(e) = result {
+                            error!("read query file fail: {}", e);
+                            valid = false;
+                        }
+                    }
+                    if !valid {
+                        continue;
+                    }
+                    if let Some(expectation) = expectation {
+                        match Name::from_str(domain) {
+                            Ok(name) => {
+                                let name = name.append_domain(&Name::root()).to_lowercase();
+                                expectations
+                                    .insert((name.to_string(), u16::from(qty)), expectation);
+                            }
+                            Err(e) => error!("read query file fail: {}", e),
+                        }
+                    }
+                    match Cache::build_packet_with_options(domain.to_string(), qty, args, &options)
+                    {
                         Some(mut v) => {
                             let random_id = {
                                 if args.packet_id == 0 {
@@ -62,7 +169,7 @@ impl Cache {
                 }
             }
         }
-        query_data
+        (query_data, has_options, expectations)
     }
     pub fn new_from_argument(args: &Argument) -> Vec<(Vec<u8>, u16)> {
         let domain = args.domain.clone();
@@ -75,24 +182,162 @@ impl Cache {
                 Protocol::UDP | Protocol::DOH => 0,
             }
         };
-        if args.packet_id == 0 {
-            for i in 0u16..=65534 {
-                let random_id = [(i >> 8) as u8, (i & 0x00ff) as u8];
-                if let Some(mut v) = Cache::build_packet(domain.clone(), qty, args) {
-                    v[offset] = random_id[0];
-                    v[offset + 1] = random_id[1];
-                    query_data.push((v, u16::from(qty)));
+        let random_id = args.packet_id.to_be_bytes();
+        if let Some(mut v) = Cache::build_packet(domain, qty, args) {
+            v[offset] = random_id[0];
+            v[offset + 1] = random_id[1];
+            query_data.push((v, u16::from(qty)));
+        }
+        query_data
+    }
+
+    /// read the raw wire messages from argument and query file, the messages
+    /// are sent byte-for-byte and only the message id may be rewritten.
+    pub fn new_from_raw(args: &Argument) -> Vec<(Vec<u8>, u16)> {
+        let mut messages = args.raw.clone();
+        if args.raw_file {
+            if let Ok(lines) = read_lines(&args.file) {
+                for line in lines.map_while(Result::ok) {
+                    if !line.trim().is_empty() && !line.starts_with('#') {
+                        messages.push(line);
+                    }
                 }
             }
-        } else {
-            let random_id = args.packet_id.to_be_bytes();
-            if let Some(mut v) = Cache::build_packet(domain.clone(), qty, args) {
-                v[offset] = random_id[0];
-                v[offset + 1] = random_id[1];
-                query_data.push((v, u16::from(qty)));
+        }
+        let mut raw_data = vec![];
+        for message in messages.iter() {
+            let mut v = match parse_raw_message(message) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("read raw message fail: {}", e);
+                    continue;
+                }
+            };
+            if args.packet_id != 0 && !args.keep_id {
+                v[0..2].copy_from_slice(&args.packet_id.to_be_bytes());
+            }
+            let qtype = question_type(&v);
+            match args.protocol {
+                Protocol::TCP | Protocol::DOT => raw_data.push((add_tcp_length(v), qtype)),
+                Protocol::UDP | Protocol::DOH => raw_data.push((v, qtype)),
             }
         }
-        query_data
+        raw_data
+    }
+
+    /// build the update packets from the template, the records are added
+    /// to the zone one by one and then deleted in the same order.
+    pub fn new_from_update(args: &Argument) -> Vec<(Vec<u8>, u16)> {
+        let mut update_data = vec![];
+        let template = match UpdateTemplate::new(args.zone.as_str(), args.update_template.as_str())
+        {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{}", e);
+                return update_data;
+            }
+        };
+        let options = EdnsOptions::new(args);
+        for delete in [false, true].iter() {
+            for index in 0..args.update_records {
+                let message = if *delete {
+                    template.delete(index)
+                } else {
+                    template.add(index)
+                };
+                match message {
+                    Ok(message) => {
+                        if let Some(v) = Cache::encode_message(message, args, &options) {
+                            update_data.push((v, u16::from(RecordType::SOA)));
+                        }
+                    }
+                    Err(e) => error!("build update message fail: {}", e),
+                }
+            }
+        }
+        update_data
+    }
+
+    /// build the zone transfer query, the IXFR query carries the SOA record
+    /// with the serial of client in authority section (RFC 1995).
+    pub fn new_from_transfer(args: &Argument) -> Vec<(Vec<u8>, u16)> {
+        let zone = match Name::from_str(args.zone.as_str()) {
+            Ok(v) => v.append_domain(&Name::root()),
+            Err(e) => {
+                error!("zone {} parse fail: {}", args.zone, e);
+                return vec![];
+            }
+        };
+        let qty = match args.mode {
+            Mode::Ixfr => RecordType::IXFR,
+            _ => RecordType::AXFR,
+        };
+        let mut message = Message::new();
+        let mut query = Query::default();
+        query.set_name(zone.clone());
+        query.set_query_type(qty);
+        message.add_query(query);
+        if qty == RecordType::IXFR {
+            let soa = SOA::new(Name::root(), Name::root(), args.ixfr_serial, 0, 0, 0, 0);
+            message.add_name_server(Record::from_rdata(zone, 0, RData::SOA(soa)));
+        }
+        match Cache::encode_message(message, args, &EdnsOptions::new(args)) {
+            Some(v) => vec![(v, u16::from(qty))],
+            None => vec![],
+        }
+    }
+
+    /// build the NOTIFY packets (RFC 1996) of the zones set by argument or
+    /// file, each line of file is "zone [serial]".
+    pub fn new_from_notify(args: &Argument) -> Vec<(Vec<u8>, u16)> {
+        let mut zones = vec![];
+        for zone in args.zone.split(',').filter(|z| !z.is_empty()) {
+            zones.push((zone.to_string(), args.notify_serial));
+        }
+        if !args.file.is_empty() {
+            if let Ok(lines) = read_lines(&args.file) {
+                for line in lines.map_while(Result::ok) {
+                    let mut splitter = line.split_whitespace();
+                    let zone = match splitter.next() {
+                        Some(v) => v.to_string(),
+                        None => continue,
+                    };
+                    match splitter.next().map(|v| v.parse::<u32>()) {
+                        Some(Ok(serial)) => zones.push((zone, Some(serial))),
+                        Some(Err(e)) => error!("read zone file fail: {}", e),
+                        None => zones.push((zone, args.notify_serial)),
+                    }
+                }
+            }
+        }
+        let options = EdnsOptions::new(args);
+        let mut notify_data = vec![];
+        for (zone, serial) in zones {
+            let zone = match Name::from_str(zone.as_str()) {
+                Ok(v) => v.append_domain(&Name::root()),
+                Err(e) => {
+                    error!("zone {} parse fail: {}", zone, e);
+                    continue;
+                }
+            };
+            let mut message = Message::new();
+            message
+                .set_op_code(OpCode::Notify)
+                .set_authoritative(true)
+                .set_recursion_desired(false);
+            let mut query = Query::default();
+            query.set_name(zone.clone());
+            query.set_query_type(RecordType::SOA);
+            message.add_query(query);
+            if let Some(serial) = serial {
+                let soa = SOA::new(Name::root(), Name::root(), serial, 0, 0, 0, 0);
+                message.add_answer(Record::from_rdata(zone, 0, RData::SOA(soa)));
+            }
+            if let Some(v) = Cache::encode_message(message, args, &options) {
+                notify_data.push((v, u16::from(RecordType::SOA)));
+            }
+        }
+        notify_data
     }
 
     pub(crate) fn build_packet(
@@ -100,7 +345,16 @@ impl Cache {
         qty: RecordType,
         args: &Argument,
     ) -> Option<Vec<u8>> {
-        let ref mut message = Message::new();
+        Cache::build_packet_with_options(domain, qty, args, &EdnsOptions::new(args))
+    }
+
+    pub(crate) fn build_packet_with_options(
+        domain: String,
+        qty: RecordType,
+        args: &Argument,
+        options: &EdnsOptions,
+    ) -> Option<Vec<u8>> {
+        let mut message = Message::new();
         let mut query = Query::default();
         let name = match Name::from_str(domain.clone().as_str()) {
             Ok(name) => name,
@@ -111,23 +365,41 @@ impl Cache {
         message.add_query(query);
         message.set_recursion_desired(!args.disable_rd);
         message.set_checking_disabled(args.enable_cd);
-        if args.disable_edns == true {
+        Cache::encode_message(message, args, options)
+    }
+
+    /// set the edns of message and encode it for the protocol
+    fn encode_message(
+        mut message: Message,
+        args: &Argument,
+        options: &EdnsOptions,
+    ) -> Option<Vec<u8>> {
+        if !args.disable_edns {
             let mut edns = Edns::default();
             edns.set_dnssec_ok(args.enable_dnssec);
             edns.set_max_payload(args.edns_size);
+            if options.nsid {
+                edns.options_mut()
+                    .insert(EdnsOption::Unknown(u16::from(EdnsCode::NSID), vec![]));
+            }
+            if options.keepalive {
+                edns.options_mut()
+                    .insert(EdnsOption::Unknown(u16::from(EdnsCode::Keepalive), vec![]));
+            }
+            for option in options.options.iter() {
+                edns.options_mut()
+                    .insert(EdnsOption::Unknown(option.code, option.data.clone()));
+            }
             message.set_edns(edns);
         }
         let protocol = args.protocol.clone();
         if let Ok(mut raw) = message.to_vec() {
+            if !args.disable_edns && options.padding > 0 {
+                pad_message(&mut raw, options.padding, 0);
+            }
             return match protocol {
                 Protocol::UDP | Protocol::DOH => Some(raw),
-                Protocol::TCP | Protocol::DOT => {
-                    let size = raw.len();
-                    let mut raw_with_size: Vec<u8> =
-                        [((size & 0xff00) >> 8) as u8, (size & 0x00ff) as u8].to_vec();
-                    raw_with_size.append(&mut raw);
-                    Some(raw_with_size)
-                }
+                Protocol::TCP | Protocol::DOT => Some(add_tcp_length(raw)),
             };
         } else {
             None
@@ -135,47 +407,86 @@ impl Cache {
     }
     pub fn new(argument: &Argument) -> Cache {
         // let domain = argument.domain.clone();
-        let offset = {
-            match argument.protocol {
-                Protocol::TCP | Protocol::DOT => 2,
-                Protocol::UDP | Protocol::DOH => 0,
+        if argument.mode == Mode::Update {
+            let cache = Cache::new_from_update(argument);
+            let size = cache.len();
+            Cache {
+                cache,
+                counter: 0,
+                size,
+                parse_full_response: false,
+                expectations: Default::default(),
             }
-        };
-        if argument.file.is_empty() {
+        } else if argument.mode == Mode::Axfr || argument.mode == Mode::Ixfr {
+            let cache = Cache::new_from_transfer(argument);
+            let size = cache.len();
+            Cache {
+                cache,
+                counter: 0,
+                size,
+                parse_full_response: false,
+                expectations: Default::default(),
+            }
+        } else if argument.mode == Mode::Notify {
+            let cache = Cache::new_from_notify(argument);
+            let size = cache.len();
+            Cache {
+                cache,
+                counter: 0,
+                size,
+                parse_full_response: false,
+                expectations: Default::default(),
+            }
+        } else if argument.raw_input() {
+            let cache = Cache::new_from_raw(argument);
+            let size = cache.len();
+            Cache {
+                cache,
+                counter: 0,
+                size,
+                parse_full_response: false,
+                expectations: Default::default(),
+            }
+        } else if argument.file.is_empty() {
             let cache = Cache::new_from_argument(argument);
             let size = cache.len();
             Cache {
-                need_rebuild: false,
                 cache,
                 counter: 0,
                 size,
-                offset,
+                parse_full_response: false,
+                expectations: Default::default(),
             }
         } else {
-            let cache = Cache::new_from_file(argument);
+            let (cache, has_options, expectations) = Cache::new_from_file(argument);
             let size = cache.len();
             Cache {
-                need_rebuild: argument.packet_id == 0,
                 cache,
                 counter: 0,
                 size,
-                offset,
+                parse_full_response: has_options || !expectations.is_empty(),
+                expectations,
             }
         }
     }
+    /// the query file set options which need parse the whole response
+    pub fn parse_full_response(&self) -> bool {
+        self.parse_full_response
+    }
+    pub fn expectations(&self) -> &Expectations {
+        &self.expectations
+    }
+    /// whether no query is built, for example the query file is empty
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
     fn get_random_id() -> [u8; 2] {
         let mut rng = rand::thread_rng();
         [rng.gen::<u8>(), rng.gen::<u8>()]
     }
     pub fn build_message(&mut self) -> (&[u8], u16) {
         self.counter += 1;
-        let ref mut data = self.cache[self.counter % self.size];
-        if self.need_rebuild == true {
-            // let id = Cache::get_random_id();
-            let id:u16 = (self.counter % 65534) as u16;
-            data.0[self.offset] =  ((id & 0xff00) >> 8) as u8;
-            data.0[self.offset + 1] = (id & 0x00ff) as u8;
-        }
+        let ref data = self.cache[self.counter % self.size];
         (data.0.as_slice().as_ref(), data.1)
     }
 }
@@ -192,9 +503,13 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::runner::cache::Cache;
-    use crate::utils::Argument;
-    use trust_dns_client::proto::op::Message;
+    use crate::runner::cache::{Cache, EdnsOptions};
+    use crate::utils::utils::parse_hex;
+    use crate::utils::{Argument, Mode, Protocol};
+    use trust_dns_client::op::UpdateMessage;
+    use trust_dns_client::proto::op::{Message, OpCode};
+    use trust_dns_client::proto::rr::rdata::opt::EdnsCode;
+    use trust_dns_client::proto::rr::{RData, RecordType};
     #[test]
     fn test_cache() {
         let arg = Argument::default();
@@ -207,4 +522,155 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_cache_from_file() {
+        let path = std::env::temp_dir().join("snd-test-query.txt");
+        std::fs::write(
+            &path,
+            "example.com nsid\nexample.org MX nsid\nexample.net A padding\n",
+        )
+        .unwrap();
+        let arg = Argument {
+            file: path.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        // the lines with invalid type or padding over udp are skipped
+        let (cache, has_options, _) = Cache::new_from_file(&arg);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache[0].1, u16::from(RecordType::MX));
+        assert!(has_options);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_default_edns() {
+        // the query carries opt record with 1232 bytes payload unless
+        // --disable-edns is set
+        let arg = Argument::default();
+        let cache = Cache::new(&arg);
+        let message = Message::from_vec(cache.cache[0].0.as_slice()).unwrap();
+        let edns = message.edns().unwrap();
+        assert_eq!(edns.max_payload(), 1232);
+        assert!(!edns.dnssec_ok());
+
+        let arg = Argument {
+            disable_edns: true,
+            ..Default::default()
+        };
+        let cache = Cache::new(&arg);
+        let message = Message::from_vec(cache.cache[0].0.as_slice()).unwrap();
+        assert!(message.edns().is_none());
+    }
+
+    #[test]
+    fn test_edns_options() {
+        let arg = Argument {
+            nsid: true,
+            ..Default::default()
+        };
+        let mut options = EdnsOptions::new(&arg);
+        assert!(options.update("padding").is_err());
+        // the padding of encrypted transport
+        options.padding = 128;
+        assert!(options.update("option=65001:abcd").is_ok());
+        assert!(options.update("option=65001:abc").is_err());
+        assert!(options.update("unknown").is_err());
+        let packet = Cache::build_packet_with_options(
+            "example.com".to_string(),
+            RecordType::A,
+            &arg,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(packet.len(), 128);
+        let message = Message::from_vec(packet.as_slice()).unwrap();
+        let edns = message.edns().unwrap();
+        assert!(edns.option(EdnsCode::NSID).is_some());
+        assert!(edns.option(EdnsCode::Padding).is_some());
+        assert!(edns.option(EdnsCode::Unknown(65001)).is_some());
+        assert!(edns.option(EdnsCode::Keepalive).is_none());
+    }
+
+    #[test]
+    fn test_update_cache() {
+        let arg = Argument {
+            mode: Mode::Update,
+            zone: "example.com".to_string(),
+            update_records: 10,
+            ..Default::default()
+        };
+        let cache = Cache::new(&arg);
+        assert_eq!(cache.cache.len(), 20);
+        let add = Message::from_vec(cache.cache[0].0.as_slice()).unwrap();
+        let delete = Message::from_vec(cache.cache[10].0.as_slice()).unwrap();
+        assert_eq!(add.op_code(), OpCode::Update);
+        assert_eq!(add.updates()[0].name(), delete.updates()[0].name());
+        assert!(add.edns().is_some());
+    }
+
+    #[test]
+    fn test_transfer_cache() {
+        let arg = Argument {
+            mode: Mode::Ixfr,
+            zone: "example.com".to_string(),
+            ixfr_serial: 2021010101,
+            protocol: Protocol::TCP,
+            ..Default::default()
+        };
+        let cache = Cache::new(&arg);
+        assert_eq!(cache.cache.len(), 1);
+        let message = Message::from_vec(&cache.cache[0].0[2..]).unwrap();
+        assert_eq!(u16::from(message.queries()[0].query_type()), 251);
+        match message.name_servers()[0].rdata() {
+            RData::SOA(soa) => assert_eq!(soa.serial(), 2021010101),
+            _ => panic!("no soa record in ixfr query"),
+        }
+    }
+
+    #[test]
+    fn test_notify_cache() {
+        let arg = Argument {
+            mode: Mode::Notify,
+            zone: "example.com,example.org".to_string(),
+            notify_serial: Some(7),
+            ..Default::default()
+        };
+        let cache = Cache::new(&arg);
+        assert_eq!(cache.cache.len(), 2);
+        let message = Message::from_vec(&cache.cache[1].0).unwrap();
+        assert_eq!(message.op_code(), OpCode::Notify);
+        assert!(message.authoritative());
+        assert_eq!(message.queries()[0].name().to_string(), "example.org.");
+        match message.answers()[0].rdata() {
+            RData::SOA(soa) => assert_eq!(soa.serial(), 7),
+            _ => panic!("no soa record in notify"),
+        }
+    }
+
+    #[test]
+    fn test_raw_cache() {
+        // two questions and the unassigned opcode 3
+        let raw = "123418000002000000000000076578616d706c6503636f6d0000010001c00c000f0001";
+        let arg = Argument {
+            raw: vec![raw.to_string()],
+            keep_id: true,
+            protocol: Protocol::TCP,
+            ..Default::default()
+        };
+        let mut cache = Cache::new(&arg);
+        assert_eq!(cache.cache.len(), 1);
+        let (packet, qtype) = cache.build_message();
+        assert_eq!(qtype, u16::from(RecordType::A));
+        assert_eq!(&packet[..2], &[0, 35]);
+        assert_eq!(packet[2..], parse_hex(raw).unwrap()[..]);
+
+        let arg = Argument {
+            raw: vec![raw.to_string()],
+            packet_id: 7,
+            ..Default::default()
+        };
+        let mut cache = Cache::new(&arg);
+        assert_eq!(cache.build_message().0[..2], [0, 7]);
+    }
 }
@@ -0,0 +1,265 @@
+use crate::runner::report::StatusStore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+
+/// SearchMethod is how the next query rate is chosen in capacity search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchMethod {
+    // increase the rate by step until a step fails
+    Ramp,
+    // bisect between the passed and failed rate until they are close as step
+    Binary,
+}
+
+impl FromStr for SearchMethod {
+    type Err = String;
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        match method.to_uppercase().as_str() {
+            "RAMP" => Ok(SearchMethod::Ramp),
+            "BINARY" => Ok(SearchMethod::Binary),
+            _ => Err(format!("capacity search method {} not valid", method)),
+        }
+    }
+}
+
+impl fmt::Display for SearchMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchMethod::Ramp => write!(f, "ramp"),
+            SearchMethod::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+/// Threshold is the service level each step must meet, the percents are
+/// relative to the queries sent and 0 latency means not checked.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub max_loss: f64,
+    pub max_timeout: f64,
+    pub max_p99: f64,
+}
+
+/// StepResult is the status of the run at one query rate
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StepResult {
+    pub qps: usize,
+    pub query_total: u64,
+    pub response_total: u64,
+    // the percent of queries without response
+    pub loss: f64,
+    // the percent of queries not answered in the timeout
+    pub timeout: f64,
+    // the 99% latency in milliseconds
+    pub p99: f64,
+    pub passed: bool,
+}
+
+impl StepResult {
+    pub fn new(
+        qps: usize,
+        query: &StatusStore,
+        response: &StatusStore,
+        threshold: &Threshold,
+    ) -> StepResult {
+        let query_total = query.query_total();
+        let response_total = response.receive_total().min(query_total);
+        let percent = |count: u64| {
+            if query_total == 0 {
+                100.0
+            } else {
+                count as f64 * 100.0 / query_total as f64
+            }
+        };
+        let loss = percent(query_total - response_total);
+        let timeout = percent(response.timeout_total());
+        let p99 = response
+            .histogram_report()
+            .map_or(0.0, |report| report.percent99 * 1000.0);
+        StepResult {
+            qps,
+            query_total,
+            response_total,
+            loss,
+            timeout,
+            p99,
+            passed: query_total > 0
+                && loss <= threshold.max_loss
+                && timeout <= threshold.max_timeout
+                && (threshold.max_p99 == 0.0 || p99 <= threshold.max_p99),
+        }
+    }
+}
+
+/// CapacitySearch chooses the query rate of each step by the results of
+/// the steps before, the search ends when next_rate returns None.
+pub struct CapacitySearch {
+    method: SearchMethod,
+    max: usize,
+    step: usize,
+    // the highest rate passed and the lowest rate failed
+    passed: Option<usize>,
+    failed: Option<usize>,
+    next: Option<usize>,
+}
+
+impl CapacitySearch {
+    pub fn new(method: SearchMethod, min: usize, max: usize, step: usize) -> CapacitySearch {
+        CapacitySearch {
+            method,
+            max,
+            step,
+            passed: None,
+            failed: None,
+            next: Some(min),
+        }
+    }
+
+    pub fn next_rate(&self) -> Option<usize> {
+        self.next
+    }
+
+    /// the highest rate which met the threshold
+    pub fn result(&self) -> Option<usize> {
+        self.passed
+    }
+
+    pub fn record(&mut self, rate: usize, passed: bool) {
+        if passed {
+            self.passed = Some(self.passed.map_or(rate, |p| p.max(rate)));
+        } else {
+            self.failed = Some(self.failed.map_or(rate, |f| f.min(rate)));
+        }
+        self.next = match (self.method, self.passed, self.failed) {
+            // the minimum rate fails, nothing to search
+            (_, None, _) => None,
+            (SearchMethod::Ramp, Some(_), Some(_)) => None,
+            (_, Some(passed), None) if passed >= self.max => None,
+            (SearchMethod::Ramp, Some(passed), None) => Some((passed + self.step).min(self.max)),
+            (SearchMethod::Binary, Some(_), None) => Some(self.max),
+            (SearchMethod::Binary, Some(passed), Some(failed)) => {
+                if failed <= passed + self.step {
+                    None
+                } else {
+                    Some(passed + (failed - passed) / 2)
+                }
+            }
+        };
+    }
+}
+
+/// CapacityReport is the maximum rate found and the curve leads to it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CapacityReport {
+    pub method: String,
+    pub threshold: Threshold,
+    pub max_qps: Option<usize>,
+    pub curve: Vec<StepResult>,
+}
+
+impl CapacityReport {
+    /// the one line summary of a step printed while searching
+    pub fn step_line(step: &StepResult) -> String {
+        format!(
+            "{:>10} {:>10} {:>10} {:>8.2}% {:>8.2}% {:>10.2}ms  {}",
+            step.qps,
+            step.query_total,
+            step.response_total,
+            step.loss,
+            step.timeout,
+            step.p99,
+            if step.passed { "pass" } else { "fail" }
+        )
+    }
+
+    pub fn header_line() -> String {
+        format!(
+            "{:>10} {:>10} {:>10} {:>9} {:>9} {:>12}  {}",
+            "QPS", "Query", "Response", "Loss", "Timeout", "99% Latency", "Result"
+        )
+    }
+
+    pub fn report(&self, output: String) {
+        let target = output.to_ascii_lowercase();
+        let formatted = if target.ends_with(".json") {
+            serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+        } else if target.ends_with(".yaml") {
+            serde_yaml::to_string(self).map_err(|e| e.to_string())
+        } else {
+            let mut out_put = format!(
+                "------------   Capacity   ------------
+          Method: {}
+        Max Loss: {}%
+     Max Timeout: {}%
+ Max 99% Latency: {}
+         Max QPS: {}
+{}",
+                self.method,
+                self.threshold.max_loss,
+                self.threshold.max_timeout,
+                if self.threshold.max_p99 == 0.0 {
+                    "unlimited".to_owned()
+                } else {
+                    format!("{}ms", self.threshold.max_p99)
+                },
+                match self.max_qps {
+                    Some(qps) => qps.to_string(),
+                    None => "none of the rates met the threshold".to_owned(),
+                },
+                CapacityReport::header_line(),
+            );
+            for step in self.curve.iter() {
+                out_put += &format!("\n{}", CapacityReport::step_line(step));
+            }
+            println!("{}", out_put);
+            return;
+        };
+        match formatted {
+            Err(err) => error!("capacity report convert fail: {}", err),
+            Ok(v) => {
+                let mut buffer = File::create(output).expect("create file error");
+                if let Err(e) = buffer.write_all(v.as_bytes()) {
+                    error!("{}", e)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::capacity::{CapacitySearch, SearchMethod};
+
+    fn search(method: SearchMethod, capacity: usize) -> (Option<usize>, Vec<usize>) {
+        let mut search = CapacitySearch::new(method, 100, 1000, 50);
+        let mut curve = vec![];
+        while let Some(rate) = search.next_rate() {
+            curve.push(rate);
+            search.record(rate, rate <= capacity);
+        }
+        (search.result(), curve)
+    }
+
+    #[test]
+    fn test_capacity_search() {
+        let (result, curve) = search(SearchMethod::Ramp, 420);
+        assert_eq!(result, Some(400));
+        assert_eq!(curve, vec![100, 150, 200, 250, 300, 350, 400, 450]);
+
+        let (result, curve) = search(SearchMethod::Binary, 420);
+        assert_eq!(curve, vec![100, 1000, 550, 325, 437, 381, 409]);
+        assert_eq!(result, Some(409));
+
+        assert_eq!(
+            search(SearchMethod::Binary, 2000),
+            (Some(1000), vec![100, 1000])
+        );
+        assert_eq!(search(SearchMethod::Ramp, 50), (None, vec![100]));
+        assert_eq!(search(SearchMethod::Ramp, 980).0, Some(950));
+        assert_eq!(search(SearchMethod::Ramp, 1000).1.last(), Some(&1000));
+        assert!("bisect".parse::<SearchMethod>().is_err());
+    }
+}
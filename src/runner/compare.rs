@@ -0,0 +1,132 @@
+use trust_dns_client::op::Message;
+
+/// the difference categories between the responses of two servers
+pub const RCODE: &str = "rcode";
+pub const FLAGS: &str = "flags";
+pub const ANSWER: &str = "answer";
+pub const NO_RESPONSE: &str = "no-response";
+pub const BOTH_TIMEOUT: &str = "both-timeout";
+
+fn flags(message: &Message) -> String {
+    let mut flags = vec![];
+    for (name, set) in [
+        ("aa", message.authoritative()),
+        ("tc", message.truncated()),
+        ("rd", message.recursion_desired()),
+        ("ra", message.recursion_available()),
+        ("ad", message.authentic_data()),
+        ("cd", message.checking_disabled()),
+    ]
+    .iter()
+    {
+        if *set {
+            flags.push(*name);
+        }
+    }
+    flags.join(" ")
+}
+
+// the answer records without TTL which is different between the caches
+fn answers(message: &Message) -> Vec<String> {
+    let mut answers: Vec<String> = message
+        .answers()
+        .iter()
+        .map(|r| {
+            format!(
+                "{} {} {} {}",
+                r.name().to_lowercase(),
+                r.dns_class(),
+                r.record_type(),
+                r.rdata()
+            )
+        })
+        .collect();
+    answers.sort();
+    answers
+}
+
+/// compare the responses of primary and secondary server, returns the
+/// differences with category, the answer RRsets are compared in any order.
+pub fn compare_messages(primary: &Message, secondary: &Message) -> Vec<(&'static str, String)> {
+    let mut differences = vec![];
+    if primary.response_code() != secondary.response_code() {
+        differences.push((
+            RCODE,
+            format!(
+                "{} != {}",
+                primary.response_code(),
+                secondary.response_code()
+            ),
+        ));
+    }
+    let (primary_flags, secondary_flags) = (flags(primary), flags(secondary));
+    if primary_flags != secondary_flags {
+        differences.push((
+            FLAGS,
+            format!("[{}] != [{}]", primary_flags, secondary_flags),
+        ));
+    }
+    let (primary_answers, secondary_answers) = (answers(primary), answers(secondary));
+    if primary_answers != secondary_answers {
+        let only = |a: &Vec<String>, b: &Vec<String>| {
+            a.iter()
+                .filter(|v| !b.contains(v))
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        differences.push((
+            ANSWER,
+            format!(
+                "-[{}] +[{}]",
+                only(&primary_answers, &secondary_answers),
+                only(&secondary_answers, &primary_answers)
+            ),
+        ));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::compare::{compare_messages, ANSWER, FLAGS, RCODE};
+    use trust_dns_client::op::{Message, Query, ResponseCode};
+    use trust_dns_client::rr::{Name, RData, Record, RecordType};
+
+    #[test]
+    fn test_compare_messages() {
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        let mut primary = Message::new();
+        primary.add_query(Query::query(name.clone(), RecordType::A));
+        let mut secondary = primary.clone();
+        for ip in ["192.0.2.1", "192.0.2.2"].iter() {
+            primary.add_answer(Record::from_rdata(
+                name.clone(),
+                300,
+                RData::A(ip.parse().unwrap()),
+            ));
+        }
+        for ip in ["192.0.2.2", "192.0.2.1"].iter() {
+            secondary.add_answer(Record::from_rdata(
+                name.clone(),
+                60,
+                RData::A(ip.parse().unwrap()),
+            ));
+        }
+        assert!(compare_messages(&primary, &secondary).is_empty());
+
+        secondary.take_answers();
+        secondary.add_answer(Record::from_rdata(
+            name,
+            60,
+            RData::A("192.0.2.1".parse().unwrap()),
+        ));
+        secondary.set_response_code(ResponseCode::ServFail);
+        secondary.set_recursion_available(true);
+        let differences = compare_messages(&primary, &secondary);
+        let categories: Vec<_> = differences.iter().map(|d| d.0).collect();
+        assert_eq!(categories, vec![RCODE, FLAGS, ANSWER]);
+        assert_eq!(differences[1].1, "[] != [ra]");
+        assert_eq!(differences[2].1, "-[www.example.com. IN A 192.0.2.2] +[]");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::runner::expect::{find_expectation, Expectations};
 use crate::runner::histogram::Histogram;
 use crate::runner::report::StatusStore;
 // use crate::utils::{Argument, Protocol};
@@ -6,6 +7,7 @@ use crate::workers::MessageOrHeader;
 pub struct ResponseConsumer {
     pub store: StatusStore,
     pub histogram: Histogram,
+    expectations: Expectations,
 }
 
 impl ResponseConsumer {
@@ -13,27 +15,48 @@ impl ResponseConsumer {
         ResponseConsumer {
             store: StatusStore::new(),
             histogram: Histogram::new(50),
+            expectations: Default::default(),
+        }
+    }
+    /// set the expectations of query file which the responses are checked
+    /// against, the whole response must be parsed.
+    pub fn set_expectations(&mut self, expectations: Expectations) {
+        self.expectations = expectations;
+    }
+    // only the sampled responses carry the latency, the timeouts are
+    // counted by the workers when the queries expire
+    fn update_latency(&mut self, elapse: f64) {
+        if elapse != 0.0 {
+            self.histogram.add(elapse);
         }
     }
     pub fn receive(&mut self, message: &MessageOrHeader) {
         match message {
             MessageOrHeader::Message((m, elapse)) => {
                 self.store.update_response_from_message(&m);
-                if *elapse != 0.0 {
-                    self.histogram.add(*elapse);
+                if let Some(expectation) = find_expectation(&self.expectations, m) {
+                    let diff = expectation.check(m).err().map(|diff| {
+                        let query = &m.queries()[0];
+                        format!("{} {}: {}", query.name(), query.query_type(), diff)
+                    });
+                    self.store.expectation.update(diff);
                 }
+                self.update_latency(*elapse);
             }
             MessageOrHeader::Header((h, elapse)) => {
                 self.store.update_response_from_header(&h);
-                if *elapse != 0.0 {
-                    self.histogram.add(*elapse);
-                }
+                self.update_latency(*elapse);
             }
             MessageOrHeader::End => {
                 self.update_report();
             }
         }
     }
+    /// drop the status and latency of responses received so far
+    pub fn reset(&mut self) {
+        self.store = StatusStore::new();
+        self.histogram = Histogram::new(50);
+    }
     pub fn update_report(&mut self) {
         self.store.update_histogram_report(self.histogram.report());
     }
@@ -0,0 +1,314 @@
+use crate::runner::cache::Cache;
+use crate::runner::rate::Arrival;
+use crate::utils::Argument;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use trust_dns_client::proto::rr::RecordType;
+use trust_dns_client::rr::Name;
+
+/// Command is one line sent to the control socket
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // change the total query rate, 0 means unlimited
+    Qps(usize),
+    Pause,
+    Resume,
+    // send the queries of file instead
+    File(String),
+    // send the query of domain with the type instead
+    Domain(String, String),
+    // stop sending and report after the last responses
+    Stop,
+}
+
+impl FromStr for Command {
+    type Err = String;
+    /// parse the command "qps <qps>", "pause", "resume", "file <file>",
+    /// "domain <domain> [type]" or "stop"
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        let items: Vec<&str> = command.split_whitespace().collect();
+        let name = items.first().map_or("".to_string(), |v| v.to_lowercase());
+        match (name.as_str(), items.len()) {
+            ("qps", 2) => match items[1].parse::<usize>() {
+                Ok(qps) => Ok(Command::Qps(qps)),
+                Err(_) => Err(format!("qps {} not valid", items[1])),
+            },
+            ("pause", 1) => Ok(Command::Pause),
+            ("resume", 1) => Ok(Command::Resume),
+            ("file", 2) => Ok(Command::File(items[1].to_string())),
+            ("domain", 2) => Ok(Command::Domain(items[1].to_string(), "A".to_string())),
+            ("domain", 3) => Ok(Command::Domain(
+                items[1].to_string(),
+                items[2].to_uppercase(),
+            )),
+            ("stop", 1) => Ok(Command::Stop),
+            _ => Err(format!(
+                "command {} should be qps <qps>, pause, resume, file <file>, \
+                 domain <domain> [type] or stop",
+                command.trim()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Qps(qps) => write!(f, "qps {}", qps),
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+            Command::File(file) => write!(f, "file {}", file),
+            Command::Domain(domain, qty) => write!(f, "domain {} {}", domain, qty),
+            Command::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+impl Command {
+    /// check whether the command can be applied to the run of arguments
+    pub fn validate(&self, arguments: &Argument) -> Result<(), String> {
+        match self {
+            Command::Qps(_) if arguments.closed_loop => {
+                Err("qps is ignored in closed loop".to_string())
+            }
+            Command::Qps(_) if matches!(arguments.arrival, Arrival::Burst(..)) => {
+                Err("qps is ignored with burst arrival".to_string())
+            }
+            Command::Qps(qps) => arguments.check_target(match qps {
+                0 => None,
+                qps => Some(*qps as f64),
+            }),
+            Command::File(_) | Command::Domain(..) => {
+                if let Command::Domain(domain, qty) = self {
+                    Name::from_str(domain)
+                        .map_err(|e| format!("domain name {} parse fail: {}", domain, e))?;
+                    RecordType::from_str(qty)
+                        .map_err(|e| format!("query type {} parse fail: {}", qty, e))?;
+                }
+                if Cache::new(&self.source(arguments)).is_empty() {
+                    return Err(format!("no query found in {}", self));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// the arguments with the query source of command, the raw messages are
+    /// replaced too.
+    pub fn source(&self, arguments: &Argument) -> Argument {
+        let mut arguments = arguments.clone();
+        match self {
+            Command::File(file) => {
+                arguments.file = file.clone();
+                arguments.raw = vec![];
+            }
+            Command::Domain(domain, qty) => {
+                arguments.file = "".to_string();
+                arguments.raw = vec![];
+                arguments.raw_file = false;
+                arguments.domain = domain.clone();
+                arguments.qty = qty.clone();
+            }
+            _ => {}
+        }
+        arguments
+    }
+}
+
+/// Control passes the commands from control socket to the producers of
+/// all the workers, each producer applies the commands it has not seen.
+#[derive(Debug, Default)]
+pub struct Control {
+    commands: Mutex<Vec<Command>>,
+    count: AtomicUsize,
+    // the reason of the first abort
+    abort: Mutex<Option<String>>,
+}
+
+impl Control {
+    pub fn new() -> Control {
+        Default::default()
+    }
+
+    pub fn push(&self, command: Command) {
+        let mut commands = self.commands.lock().unwrap();
+        commands.push(command);
+        self.count.store(commands.len(), Ordering::Release);
+    }
+
+    /// the commands after the seen ones, it only locks when there is any
+    pub fn commands_since(&self, seen: usize) -> Vec<Command> {
+        if self.count.load(Ordering::Acquire) <= seen {
+            return vec![];
+        }
+        self.commands.lock().unwrap()[seen..].to_vec()
+    }
+
+    /// stop all the workers for the reason, the first reason is kept
+    pub fn abort(&self, reason: String) {
+        let mut abort = self.abort.lock().unwrap();
+        if abort.is_none() {
+            warn!("abort the run: {}", reason);
+            *abort = Some(reason);
+            self.push(Command::Stop);
+        }
+    }
+
+    pub fn abort_reason(&self) -> Option<String> {
+        self.abort.lock().unwrap().clone()
+    }
+
+    /// whether the stop command is pushed
+    pub fn stopped(&self) -> bool {
+        self.commands.lock().unwrap().contains(&Command::Stop)
+    }
+
+    /// push the stop command on the first SIGINT or SIGTERM so the run ends
+    /// with the report, the second signal exits at once.
+    pub fn stop_on_signal(control: Arc<Control>) -> Result<(), String> {
+        let interrupted = AtomicBool::new(false);
+        ctrlc::set_handler(move || {
+            if interrupted.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            println!(
+                "stop sending and wait for the last responses, interrupt again to exit at once"
+            );
+            control.push(Command::Stop);
+        })
+        .map_err(|e| format!("set signal handler fail: {}", e))
+    }
+}
+
+/// ControlServer accepts the commands on unix socket, one command each line
+/// and the reply is "ok" or "error: <reason>". The socket file is removed
+/// when the server is dropped.
+pub struct ControlServer {
+    path: String,
+}
+
+impl ControlServer {
+    pub fn start(arguments: &Argument, control: Arc<Control>) -> Result<ControlServer, String> {
+        let path = arguments.control_socket.clone();
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| format!("bind control socket {} fail: {}", path, e))?;
+        let arguments = arguments.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let arguments = arguments.clone();
+                        let control = control.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = serve(stream, &arguments, &control) {
+                                debug!("control connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => error!("accept control connection fail: {}", e),
+                }
+            }
+        });
+        Ok(ControlServer { path })
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("remove control socket {} fail: {}", self.path, e);
+        }
+    }
+}
+
+fn serve(stream: UnixStream, arguments: &Argument, control: &Control) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match line
+            .parse::<Command>()
+            .and_then(|command| command.validate(arguments).map(|_| command))
+        {
+            Ok(command) => {
+                info!("control command: {}", command);
+                control.push(command);
+                "ok".to_string()
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::control::{Command, Control};
+    use crate::runner::rate::Arrival;
+    use crate::utils::Argument;
+
+    #[test]
+    fn test_command() {
+        assert_eq!("qps 500".parse::<Command>(), Ok(Command::Qps(500)));
+        assert_eq!(" PAUSE ".parse::<Command>(), Ok(Command::Pause));
+        assert_eq!(
+            "domain example.org aaaa".parse::<Command>(),
+            Ok(Command::Domain(
+                "example.org".to_string(),
+                "AAAA".to_string()
+            ))
+        );
+        assert_eq!(
+            "domain example.org".parse::<Command>().unwrap().to_string(),
+            "domain example.org A"
+        );
+        assert!("qps -1".parse::<Command>().is_err());
+        assert!("resume now".parse::<Command>().is_err());
+        assert!("".parse::<Command>().is_err());
+
+        let mut arguments = Argument::default();
+        assert!(Command::Qps(100).validate(&arguments).is_ok());
+        assert!(Command::Domain("example.org".to_string(), "MX".to_string())
+            .validate(&arguments)
+            .is_ok());
+        assert!(Command::File("/not/exist".to_string())
+            .validate(&arguments)
+            .is_err());
+        arguments.arrival = Arrival::Burst(10, 0.1);
+        assert!(Command::Qps(100).validate(&arguments).is_err());
+        assert!(Command::Domain("a..b".to_string(), "A".to_string())
+            .validate(&arguments)
+            .is_err());
+    }
+
+    #[test]
+    fn test_control() {
+        let control = Control::new();
+        assert!(control.commands_since(0).is_empty());
+        control.push(Command::Pause);
+        control.push(Command::Qps(10));
+        assert_eq!(
+            control.commands_since(0),
+            vec![Command::Pause, Command::Qps(10)]
+        );
+        assert_eq!(control.commands_since(1), vec![Command::Qps(10)]);
+        assert!(control.commands_since(2).is_empty());
+        assert!(!control.stopped());
+        control.push(Command::Stop);
+        assert!(control.stopped());
+        control.abort("loss".to_string());
+        control.abort("servfail".to_string());
+        assert_eq!(control.abort_reason(), Some("loss".to_string()));
+        assert_eq!(control.commands_since(3), vec![Command::Stop]);
+    }
+}
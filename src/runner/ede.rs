@@ -0,0 +1,84 @@
+use crate::utils::wire::find_edns_options;
+
+pub const EDE_CODE: u16 = 15;
+
+// the info codes of extended dns error (RFC 8914 section 5.2)
+const INFO_CODES: [(u16, &str); 25] = [
+    (0, "Other"),
+    (1, "Unsupported DNSKEY Algorithm"),
+    (2, "Unsupported DS Digest Type"),
+    (3, "Stale Answer"),
+    (4, "Forged Answer"),
+    (5, "DNSSEC Indeterminate"),
+    (6, "DNSSEC Bogus"),
+    (7, "Signature Expired"),
+    (8, "Signature Not Yet Valid"),
+    (9, "DNSKEY Missing"),
+    (10, "RRSIGs Missing"),
+    (11, "No Zone Key Bit Set"),
+    (12, "NSEC Missing"),
+    (13, "Cached Error"),
+    (14, "Not Ready"),
+    (15, "Blocked"),
+    (16, "Censored"),
+    (17, "Filtered"),
+    (18, "Prohibited"),
+    (19, "Stale NXDOMAIN Answer"),
+    (20, "Not Authoritative"),
+    (21, "Not Supported"),
+    (22, "No Reachable Authority"),
+    (23, "Network Error"),
+    (24, "Invalid Data"),
+];
+
+/// the info code with its name for report, for example "18(Prohibited)"
+pub fn info_code_name(code: u16) -> String {
+    match INFO_CODES.iter().find(|(value, _)| *value == code) {
+        Some((_, name)) => format!("{}({})", code, name),
+        None => format!("{}(Unknown)", code),
+    }
+}
+
+/// the info code and extra text of the extended dns errors in response,
+/// the option shorter than info code is ignored.
+pub fn extended_errors(message: &[u8]) -> Vec<(u16, String)> {
+    find_edns_options(message, EDE_CODE)
+        .iter()
+        .filter(|data| data.len() >= 2)
+        .map(|data| {
+            (
+                u16::from_be_bytes([data[0], data[1]]),
+                String::from_utf8_lossy(&data[2..])
+                    .trim_end_matches('\0')
+                    .to_string(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::ede::{extended_errors, info_code_name, EDE_CODE};
+    use crate::utils::wire::append_edns_option;
+    use trust_dns_client::op::{Edns, Message};
+
+    #[test]
+    fn test_extended_errors() {
+        let mut message = Message::new();
+        message.set_edns(Edns::new());
+        let mut raw = message.to_vec().unwrap();
+        assert!(extended_errors(&raw).is_empty());
+        append_edns_option(&mut raw, EDE_CODE, &[0, 22]);
+        append_edns_option(&mut raw, EDE_CODE, b"\x00\x09no key for example.com");
+        append_edns_option(&mut raw, EDE_CODE, &[1]);
+        assert_eq!(
+            extended_errors(&raw),
+            vec![
+                (22, "".to_string()),
+                (9, "no key for example.com".to_string())
+            ]
+        );
+        assert_eq!(info_code_name(18), "18(Prohibited)");
+        assert_eq!(info_code_name(500), "500(Unknown)");
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use trust_dns_client::op::{Message, ResponseCode};
+use trust_dns_client::rr::RecordType;
+
+// the response codes can be set in expectation (RFC 6895 section 2.3)
+const RESPONSE_CODES: [(&str, u8); 11] = [
+    ("NOERROR", 0),
+    ("FORMERR", 1),
+    ("SERVFAIL", 2),
+    ("NXDOMAIN", 3),
+    ("NOTIMP", 4),
+    ("REFUSED", 5),
+    ("YXDOMAIN", 6),
+    ("YXRRSET", 7),
+    ("NXRRSET", 8),
+    ("NOTAUTH", 9),
+    ("NOTZONE", 10),
+];
+
+/// the expectations of query file keyed by the lowercase query name with
+/// trailing dot and the query type.
+pub type Expectations = HashMap<(String, u16), Expectation>;
+
+fn parse_response_code(code: &str) -> Option<ResponseCode> {
+    RESPONSE_CODES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(code))
+        .map(|(_, value)| ResponseCode::from(0, *value))
+}
+
+fn normalize(data: &str) -> String {
+    data.trim_end_matches('.').to_lowercase()
+}
+
+/// Expectation is the expected response of a query set in query file,
+/// "expect=NOERROR,192.0.2.1" checks the response code and the answer
+/// records, "expect-rcode=NXDOMAIN" checks the response code only.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expectation {
+    rcode: Option<ResponseCode>,
+    // the sorted and normalized rdata of answers, None if not checked
+    answers: Option<Vec<String>>,
+}
+
+impl FromStr for Expectation {
+    type Err = String;
+    fn from_str(option: &str) -> Result<Self, Self::Err> {
+        if let Some(code) = option.strip_prefix("expect-rcode=") {
+            return match parse_response_code(code) {
+                Some(v) => Ok(Expectation {
+                    rcode: Some(v),
+                    answers: None,
+                }),
+                None => Err(format!("expect response code {} not valid", code)),
+            };
+        }
+        let value = match option.strip_prefix("expect=") {
+            Some(v) => v,
+            None => return Err(format!("expectation {} not valid", option)),
+        };
+        let mut items: Vec<&str> = value.split(',').filter(|v| !v.is_empty()).collect();
+        let rcode = match items.first().and_then(|v| parse_response_code(v)) {
+            Some(v) => {
+                items.remove(0);
+                v
+            }
+            None => ResponseCode::NoError,
+        };
+        let mut answers: Vec<String> = items.iter().map(|v| normalize(v)).collect();
+        answers.sort();
+        Ok(Expectation {
+            rcode: Some(rcode),
+            answers: Some(answers),
+        })
+    }
+}
+
+impl Expectation {
+    /// check the response, returns the difference if it's not expected, the
+    /// answers are compared in any order and only the records of query type
+    /// are compared, so the CNAME chain is ignored.
+    pub fn check(&self, message: &Message) -> Result<(), String> {
+        let mut diff = vec![];
+        if let Some(expected) = self.rcode {
+            if message.response_code() != expected {
+                diff.push(format!("rcode {} != {}", message.response_code(), expected));
+            }
+        }
+        if let Some(expected) = self.answers.as_ref() {
+            let qtype = message
+                .queries()
+                .first()
+                .map(|q| q.query_type())
+                .unwrap_or(RecordType::ANY);
+            let mut answers: Vec<String> = message
+                .answers()
+                .iter()
+                .filter(|r| qtype == RecordType::ANY || r.record_type() == qtype)
+                .map(|r| normalize(&r.rdata().to_string()))
+                .collect();
+            answers.sort();
+            if &answers != expected {
+                diff.push(format!(
+                    "answer [{}] != [{}]",
+                    answers.join(","),
+                    expected.join(",")
+                ));
+            }
+        }
+        if diff.is_empty() {
+            Ok(())
+        } else {
+            Err(diff.join(", "))
+        }
+    }
+}
+
+/// find the expectation of the response by its question
+pub fn find_expectation<'a>(
+    expectations: &'a Expectations,
+    message: &Message,
+) -> Option<&'a Expectation> {
+    let query = message.queries().first()?;
+    expectations.get(&(
+        query.name().to_lowercase().to_string(),
+        u16::from(query.query_type()),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::expect::Expectation;
+    use std::str::FromStr;
+    use trust_dns_client::op::{Message, Query, ResponseCode};
+    use trust_dns_client::rr::{Name, RData, Record, RecordType};
+
+    #[test]
+    fn test_expectation() {
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        let mut message = Message::new();
+        message.add_query(Query::query(name.clone(), RecordType::A));
+        message.add_answer(Record::from_rdata(
+            name.clone(),
+            300,
+            RData::CNAME(Name::from_ascii("web.example.com.").unwrap()),
+        ));
+        for ip in ["192.0.2.2", "192.0.2.1"].iter() {
+            message.add_answer(Record::from_rdata(
+                name.clone(),
+                300,
+                RData::A(ip.parse().unwrap()),
+            ));
+        }
+
+        let expect = Expectation::from_str("expect=NOERROR,192.0.2.1,192.0.2.2").unwrap();
+        assert!(expect.check(&message).is_ok());
+        let expect = Expectation::from_str("expect=192.0.2.1").unwrap();
+        assert_eq!(
+            expect.check(&message),
+            Err("answer [192.0.2.1,192.0.2.2] != [192.0.2.1]".to_string())
+        );
+        let expect = Expectation::from_str("expect-rcode=nxdomain").unwrap();
+        assert!(expect.check(&message).is_err());
+        message.set_response_code(ResponseCode::NXDomain);
+        assert!(expect.check(&message).is_ok());
+
+        assert!(Expectation::from_str("expect-rcode=UNKNOWN").is_err());
+        assert!(Expectation::from_str("nsid").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::utils::wire::find_opt_record;
+use rand::seq::index::sample;
+use rand::Rng;
+use std::fmt;
+
+const HEADER_SIZE: usize = 12;
+const OPT_TYPE: u16 = 41;
+
+/// Mutation is the class of malformed packet generated from a valid query,
+/// the first two bytes (message id) are never changed so the response can
+/// be matched with the mutation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Mutation {
+    // the valid query for checking whether the server is still alive
+    Probe,
+    HeaderBits,
+    LabelLength,
+    CompressionPointer,
+    SectionCount,
+    OversizedOpt,
+    TrailingGarbage,
+    UnknownOpcode,
+}
+
+pub const MUTATIONS: [Mutation; 7] = [
+    Mutation::HeaderBits,
+    Mutation::LabelLength,
+    Mutation::CompressionPointer,
+    Mutation::SectionCount,
+    Mutation::OversizedOpt,
+    Mutation::TrailingGarbage,
+    Mutation::UnknownOpcode,
+];
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Mutation::Probe => "probe",
+            Mutation::HeaderBits => "header-bits",
+            Mutation::LabelLength => "label-length",
+            Mutation::CompressionPointer => "compression-pointer",
+            Mutation::SectionCount => "section-count",
+            Mutation::OversizedOpt => "oversized-opt",
+            Mutation::TrailingGarbage => "trailing-garbage",
+            Mutation::UnknownOpcode => "unknown-opcode",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// MutationResult is what happened after the mutated packet sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MutationResult {
+    Sent,
+    Answer,
+    FormErr,
+    NoAnswer,
+    // the server didn't answer the probe query after the mutation
+    ServerStop,
+}
+
+impl fmt::Display for MutationResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MutationResult::Sent => "sent",
+            MutationResult::Answer => "answer",
+            MutationResult::FormErr => "formerr",
+            MutationResult::NoAnswer => "no-answer",
+            MutationResult::ServerStop => "server-stop",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Mutation {
+    /// mutate the dns message which has no tcp length prefix
+    pub fn apply(&self, message: &mut Vec<u8>) {
+        if message.len() < HEADER_SIZE {
+            return;
+        }
+        let mut rng = rand::thread_rng();
+        match self {
+            Mutation::Probe => {}
+            Mutation::HeaderBits => {
+                let amount = rng.gen_range(1..=3);
+                for bit in sample(&mut rng, (HEADER_SIZE - 2) * 8, amount).iter() {
+                    message[2 + bit / 8] ^= 0x80 >> (bit % 8);
+                }
+            }
+            Mutation::LabelLength => {
+                // the label type 0x40 and 0x80 are reserved, or the label
+                // is longer than the rest of message
+                let rest = message.len() - HEADER_SIZE;
+                if rest > 0 {
+                    message[HEADER_SIZE] = if rest > 0x3f || rng.gen() {
+                        rng.gen_range(0x40..0xc0)
+                    } else {
+                        rng.gen_range(rest as u8..=0x3f)
+                    };
+                }
+            }
+            Mutation::CompressionPointer => {
+                if message.len() > HEADER_SIZE + 1 {
+                    // points to itself, into the header or out of the message
+                    let target: u16 = match rng.gen_range(0..3) {
+                        0 => HEADER_SIZE as u16,
+                        1 => rng.gen_range(0..HEADER_SIZE as u16),
+                        _ => rng.gen_range(message.len() as u16..0x3fff),
+                    };
+                    message[HEADER_SIZE..HEADER_SIZE + 2]
+                        .copy_from_slice(&(0xc000 | target).to_be_bytes());
+                }
+            }
+            Mutation::SectionCount => {
+                let section = 4 + 2 * rng.gen_range(0..4);
+                let original = u16::from_be_bytes([message[section], message[section + 1]]);
+                let mut count: u16 = if rng.gen() { rng.gen() } else { 0 };
+                if count == original {
+                    count = original.wrapping_add(1);
+                }
+                message[section..section + 2].copy_from_slice(&count.to_be_bytes());
+            }
+            Mutation::OversizedOpt => match find_opt_record(message) {
+                Some(position) => {
+                    let size = message.len() - position - 11;
+                    let rdlength = rng.gen_range(size as u16 + 1..=u16::MAX);
+                    message[position + 9..position + 11].copy_from_slice(&rdlength.to_be_bytes());
+                }
+                None => {
+                    message.push(0);
+                    message.extend_from_slice(&OPT_TYPE.to_be_bytes());
+                    message.extend_from_slice(&u16::MAX.to_be_bytes());
+                    message.extend_from_slice(&0u32.to_be_bytes());
+                    message.extend_from_slice(&u16::MAX.to_be_bytes());
+                    let additional = u16::from_be_bytes([message[10], message[11]]);
+                    message[10..12].copy_from_slice(&additional.wrapping_add(1).to_be_bytes());
+                }
+            },
+            Mutation::TrailingGarbage => {
+                for _ in 0..rng.gen_range(1..=64) {
+                    message.push(rng.gen());
+                }
+            }
+            Mutation::UnknownOpcode => {
+                // the opcode 3 and 7-15 are unassigned
+                let opcode: u8 = match rng.gen_range(0..10) {
+                    0 => 3,
+                    v => v + 6,
+                };
+                message[2] = (message[2] & 0x87) | (opcode << 3);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::cache::Cache;
+    use crate::runner::fuzz::{Mutation, MUTATIONS};
+    use crate::utils::Argument;
+    use trust_dns_client::op::Message;
+    use trust_dns_client::rr::RecordType;
+
+    #[test]
+    fn test_mutation() {
+        let arg = Argument::default();
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+        let mut probe = packet.clone();
+        Mutation::Probe.apply(&mut probe);
+        assert_eq!(probe, packet);
+        for mutation in MUTATIONS.iter() {
+            for _ in 0..100 {
+                let mut mutated = packet.clone();
+                mutation.apply(&mut mutated);
+                assert_ne!(mutated, packet, "{} not mutated", mutation);
+                assert_eq!(mutated[0..2], packet[0..2]);
+            }
+        }
+        for mutation in [Mutation::OversizedOpt, Mutation::UnknownOpcode].iter() {
+            let mut mutated = packet.clone();
+            mutation.apply(&mut mutated);
+            assert!(Message::from_vec(&mutated).is_err(), "{}", mutation);
+        }
+    }
+}
@@ -1,9 +1,18 @@
 pub mod cache;
+pub mod capacity;
+pub mod compare;
 pub mod consumer;
+pub mod control;
+pub mod ede;
+pub mod expect;
+pub mod fuzz;
 pub mod histogram;
 pub mod producer;
+pub mod rate;
 pub mod report;
 pub mod runner;
+pub mod safeguard;
+pub mod update;
 
 pub use producer::QueryProducer;
 pub use runner::Runner;
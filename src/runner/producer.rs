@@ -1,19 +1,46 @@
 use crate::runner::cache::Cache;
+use crate::runner::control::{Command, Control};
+use crate::runner::expect::Expectations;
+use crate::runner::rate::{Arrival, Pacer, RateProfile};
 use crate::runner::report::StatusStore;
 use crate::utils::Argument;
-use governor::clock::{Clock, DefaultClock, QuantaClock, Reference};
-use governor::state::{InMemoryState, NotKeyed};
-use governor::{Quota, RateLimiter};
-use std::num::NonZeroU32;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// the time to wait before checking the control commands again when paused
+const PAUSE_WAIT: Duration = Duration::from_millis(100);
 
 pub struct QueryProducer {
     pub store: StatusStore,
     max_counter: u64,
     counter: u64,
     stop_at: u64,
-    rate_limiter: Option<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
+    // None if the query rate is unlimited
+    pacer: Option<Pacer>,
+    start: Instant,
+    // the elapsed seconds and query counter of the last rate update
+    last_rate_update: (f64, u64),
+    // the elapsed seconds of the last query
+    last_query: f64,
+    // the elapsed seconds when the producer stopped
+    stopped: Option<f64>,
+    // the elapsed seconds when the warmup ends, None after it ended
+    warmup: Option<f64>,
+    // the elapsed seconds when the measurement starts
+    origin: f64,
+    // the target rate profiles with the elapsed seconds they start from,
+    // the rate is changed by the control commands
+    profiles: Vec<(f64, RateProfile)>,
+    // the commands of control socket and the number of them applied
+    control: Option<Arc<Control>>,
+    applied: usize,
+    paused: bool,
+    stop_requested: bool,
+    // the query source is changed since the worker asked
+    source_changed: bool,
+    argument: Argument,
     cache: Cache,
+    parse_full_response: bool,
 }
 
 pub enum PacketGeneratorStatus<'a> {
@@ -30,42 +57,61 @@ impl QueryProducer {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs()
-                + argument.until_stop as u64;
+                + (argument.until_stop + argument.warmup) as u64;
         };
 
+        let cache = Cache::new(&argument.clone());
+        let pacer = match (argument.rate_profile.clone(), &argument.arrival) {
+            _ if argument.closed_loop => None,
+            (Some(profile), arrival) => Some(Pacer::new(profile, arrival.clone())),
+            (None, arrival) if argument.qps != 0 || matches!(arrival, Arrival::Burst(..)) => Some(
+                Pacer::new(RateProfile::Constant(argument.qps as f64), arrival.clone()),
+            ),
+            _ => None,
+        }
+        .map(|pacer| pacer.with_phase(argument.rate_phase));
         QueryProducer {
             store: StatusStore::new(),
             counter: 0,
             max_counter: argument.max as u64,
             stop_at,
-            rate_limiter: {
-                if argument.qps == 0 {
-                    None
-                } else {
-                    Some(RateLimiter::direct(
-                        Quota::per_second(
-                            NonZeroU32::new(argument.qps as u32).expect("qps setting error"),
-                        )
-                        .allow_burst(NonZeroU32::new(1).unwrap()),
-                    ))
-                }
+            profiles: pacer
+                .iter()
+                .map(|pacer| (0.0, pacer.profile().clone()))
+                .collect(),
+            pacer,
+            start: Instant::now(),
+            last_rate_update: (0.0, 0),
+            last_query: 0.0,
+            stopped: None,
+            warmup: if argument.warmup > 0 {
+                Some(argument.warmup as f64)
+            } else {
+                None
             },
-            cache: Cache::new(&argument.clone()),
+            origin: 0.0,
+            control: argument.control.clone(),
+            applied: 0,
+            paused: false,
+            stop_requested: false,
+            source_changed: false,
+            parse_full_response: argument.parse_full_response() || cache.parse_full_response(),
+            argument,
+            cache,
         }
     }
     pub fn retrieve(&mut self) -> PacketGeneratorStatus {
-        if let Some(limiter) = self.rate_limiter.as_ref() {
-            match limiter.check() {
-                Err(err) => {
-                    let sleep = err
-                        .earliest_possible()
-                        .duration_since(QuantaClock::default().now());
-                    return PacketGeneratorStatus::Wait(sleep.into());
-                }
-                _ => {}
-            };
+        let commands = match self.control.as_ref() {
+            Some(control) => control.commands_since(self.applied),
+            None => vec![],
+        };
+        for command in commands {
+            self.apply(command);
         }
-        if (self.max_counter != 0 && self.counter >= self.max_counter)
+        let max_reached =
+            self.warmup.is_none() && self.max_counter != 0 && self.counter >= self.max_counter;
+        if max_reached
+            || self.stop_requested
             || (self.stop_at != 0
                 && SystemTime::now()
                     .duration_since(UNIX_EPOCH)
@@ -74,13 +120,180 @@ impl QueryProducer {
                     >= self.stop_at)
         {
             self.store.set_query_total(self.counter);
+            if self.stopped.is_none() {
+                // the last query takes its interval before the next one is due
+                let now = self.start.elapsed().as_secs_f64();
+                self.stopped = Some(match self.pacer.as_ref() {
+                    Some(pacer) if max_reached => pacer.next_due().max(self.last_query),
+                    _ => now,
+                });
+            }
             return PacketGeneratorStatus::Stop;
         }
+        if self.paused {
+            return PacketGeneratorStatus::Wait(PAUSE_WAIT.as_nanos() as u64);
+        }
+        if let Some(pacer) = self.pacer.as_mut() {
+            if let Some(wait) = pacer.check() {
+                return PacketGeneratorStatus::Wait(wait.as_nanos() as u64);
+            }
+        }
         let message = self.cache.build_message();
         self.counter = self.counter + 1;
+        self.last_query = self.start.elapsed().as_secs_f64();
         PacketGeneratorStatus::Success(message.0, message.1)
     }
 
+    // apply the command of control socket, the pacer takes the share of
+    // the total rate like it is created by the runner
+    fn apply(&mut self, command: Command) {
+        self.applied += 1;
+        let now = self.start.elapsed().as_secs_f64();
+        match command {
+            Command::Qps(0) => self.pacer = None,
+            Command::Qps(qps) => {
+                let profile = RateProfile::Constant(qps as f64 * self.argument.rate_share);
+                match self.pacer.as_mut() {
+                    Some(pacer) => pacer.set_profile(profile),
+                    None => {
+                        self.pacer = Some(
+                            Pacer::new(profile, self.argument.arrival.clone())
+                                .with_phase(self.argument.rate_phase),
+                        )
+                    }
+                }
+            }
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            Command::File(_) | Command::Domain(..) => {
+                self.argument = command.source(&self.argument);
+                self.cache = Cache::new(&self.argument);
+                self.parse_full_response =
+                    self.argument.parse_full_response() || self.cache.parse_full_response();
+                self.source_changed = true;
+                return;
+            }
+            Command::Stop => {
+                self.stop_requested = true;
+                return;
+            }
+        }
+        // no query is expected while paused or the rate is unlimited
+        let profile = match self.pacer.as_ref() {
+            Some(pacer) if !self.paused => pacer.profile().clone(),
+            _ => RateProfile::Constant(0.0),
+        };
+        self.profiles.push((now, profile));
+    }
+
+    /// whether the stop command is received, the worker ends when the
+    /// queries sent are answered or expired.
+    pub fn stop_requested(&self) -> bool {
+        self.stop_requested
+    }
+
+    /// whether the producer stopped sending by any reason
+    pub fn stopped(&self) -> bool {
+        self.stopped.is_some()
+    }
+
+    /// stop the workers sharing the control for the reason
+    pub fn abort(&mut self, reason: String) {
+        if let Some(control) = self.control.as_ref() {
+            control.abort(reason);
+        }
+        self.stop_requested = true;
+    }
+
+    /// whether sending is paused by the control command
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// whether the query source is changed by the control command since
+    /// the last call, the worker should update the expectations of responses.
+    pub fn take_source_change(&mut self) -> bool {
+        std::mem::replace(&mut self.source_changed, false)
+    }
+
+    /// the expectations of responses set in query file
+    pub fn expectations(&self) -> &Expectations {
+        self.cache.expectations()
+    }
+
+    /// whether the queries are sent in the warmup period
+    pub fn warming_up(&self) -> bool {
+        self.warmup.is_some()
+    }
+
+    /// drop the status of warmup when it ends, returns true only once at
+    /// the end of warmup so the worker can drop its status too.
+    pub fn end_warmup(&mut self) -> bool {
+        let now = self.start.elapsed().as_secs_f64();
+        match self.warmup {
+            Some(warmup) if now >= warmup => {
+                self.warmup = None;
+                self.origin = now;
+                self.store = StatusStore::new();
+                self.counter = 0;
+                self.last_rate_update = (now, 0);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// whether the worker should decode the whole response message
+    pub fn parse_full_response(&self) -> bool {
+        self.parse_full_response
+    }
+
+    // the queries should be sent by the target rate between the elapsed seconds
+    fn expected(&self, from: f64, to: f64) -> f64 {
+        self.profiles
+            .iter()
+            .enumerate()
+            .map(|(i, (start, profile))| {
+                let end = self.profiles.get(i + 1).map_or(to, |next| next.0);
+                profile.expected(from.max(*start), to.min(end))
+            })
+            .sum()
+    }
+
+    /// record the target and achieved rate since the last update, the workers
+    /// call it before sending the interval status.
+    pub fn update_interval_rate(&mut self) {
+        let now = self.start.elapsed().as_secs_f64();
+        let (last, counter) = self.last_rate_update;
+        // no query is expected after the producer stopped
+        let end = self.stopped.map_or(now, |stopped| stopped.min(now));
+        if end > last {
+            self.store.rate.target = self.expected(last, end) / (now - last);
+            self.store.rate.achieved = (self.counter - counter) as f64 / (now - last);
+        } else {
+            self.store.rate.target = 0.0;
+            self.store.rate.achieved = 0.0;
+        }
+        self.last_rate_update = (now, self.counter);
+    }
+
+    /// record the target and achieved rate of the whole run until the
+    /// producer stopped, the time of waiting for the last responses after
+    /// it is not counted. The worker may finish before the producer is asked
+    /// again, then the run ends when the next query is due.
+    pub fn update_total_rate(&mut self) {
+        let end = match (self.stopped, self.pacer.as_ref()) {
+            (Some(stopped), _) => stopped,
+            (None, Some(pacer)) => pacer.next_due().max(self.last_query),
+            (None, None) => self.start.elapsed().as_secs_f64(),
+        };
+        if self.last_query > self.origin && end > self.origin {
+            let duration = end - self.origin;
+            self.store.rate.target = self.expected(self.origin, end) / duration;
+            self.store.rate.achieved = self.counter as f64 / duration;
+        }
+    }
+
     pub fn return_back(&mut self) {
         self.counter -= 1;
     }
@@ -0,0 +1,468 @@
+use rand::Rng;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// the longest time the pacer waits before checking the rate again, so the
+// change of rate is followed even if the current rate is very low
+const MAX_WAIT: f64 = 0.1;
+// the queries delayed by the event loop are sent at most this late, more
+// delay is not caught up by a burst
+const MAX_LAG: f64 = 0.01;
+// the step for integrating the rate of profile
+const INTEGRATE_STEP: f64 = 0.01;
+const MAX_INTEGRATE_STEPS: f64 = 100_000.0;
+
+/// RateProfile is the target query rate which changes over the run time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateProfile {
+    Constant(f64),
+    // ramp from the first rate to the second one in seconds
+    Linear(f64, f64, f64),
+    // the plateaus of rate, each lasts the seconds
+    Step(Vec<f64>, f64),
+    // the mean, amplitude and period in seconds of sine wave
+    Sine(f64, f64, f64),
+    // the rate from the time in seconds until the next one
+    Schedule(Vec<(f64, f64)>),
+}
+
+fn parse_number(value: &str, name: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v >= 0.0 => Ok(v),
+        _ => Err(format!("rate profile {} {} not valid", name, value)),
+    }
+}
+
+impl FromStr for RateProfile {
+    type Err = String;
+    /// parse the profile "linear:<from>:<to>:<seconds>",
+    /// "step:<rate>,<rate>,...:<seconds>" or "sine:<mean>:<amplitude>:<period>"
+    fn from_str(profile: &str) -> Result<Self, Self::Err> {
+        let items: Vec<&str> = profile.split(':').collect();
+        let profile = match (items[0].to_lowercase().as_str(), items.len()) {
+            ("linear", 4) => RateProfile::Linear(
+                parse_number(items[1], "rate")?,
+                parse_number(items[2], "rate")?,
+                parse_number(items[3], "seconds")?,
+            ),
+            ("step", 3) => RateProfile::Step(
+                items[1]
+                    .split(',')
+                    .map(|v| parse_number(v, "rate"))
+                    .collect::<Result<Vec<_>, _>>()?,
+                parse_number(items[2], "seconds")?,
+            ),
+            ("sine", 4) => RateProfile::Sine(
+                parse_number(items[1], "rate")?,
+                parse_number(items[2], "amplitude")?,
+                parse_number(items[3], "period")?,
+            ),
+            _ => {
+                return Err(format!(
+                    "rate profile {} should be linear:<from>:<to>:<seconds>, \
+                     step:<rate>,<rate>:<seconds> or sine:<mean>:<amplitude>:<period>",
+                    profile
+                ))
+            }
+        };
+        match &profile {
+            RateProfile::Linear(_, _, seconds) | RateProfile::Step(_, seconds)
+                if *seconds == 0.0 =>
+            {
+                Err("rate profile seconds must be greater than 0".to_string())
+            }
+            RateProfile::Sine(_, _, period) if *period == 0.0 => {
+                Err("rate profile period must be greater than 0".to_string())
+            }
+            _ => Ok(profile),
+        }
+    }
+}
+
+impl fmt::Display for RateProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateProfile::Constant(rate) => write!(f, "{}", rate),
+            RateProfile::Linear(from, to, seconds) => {
+                write!(f, "linear {} -> {} in {}s", from, to, seconds)
+            }
+            RateProfile::Step(rates, seconds) => {
+                let rates: Vec<_> = rates.iter().map(|r| r.to_string()).collect();
+                write!(f, "step {} every {}s", rates.join(","), seconds)
+            }
+            RateProfile::Sine(mean, amplitude, period) => {
+                write!(f, "sine {}±{} period {}s", mean, amplitude, period)
+            }
+            RateProfile::Schedule(schedule) => write!(f, "schedule of {} rates", schedule.len()),
+        }
+    }
+}
+
+impl RateProfile {
+    /// load the schedule from csv file, each line is "<seconds>,<rate>" and
+    /// the lines start with "#" or not number (the header) are ignored.
+    pub fn from_file(file: &str) -> Result<RateProfile, String> {
+        let content = fs::read_to_string(file)
+            .map_err(|e| format!("read rate schedule {} fail: {}", file, e))?;
+        let mut schedule: Vec<(f64, f64)> = vec![];
+        for line in content.lines().map(|l| l.trim()) {
+            let items: Vec<&str> = line.split(',').collect();
+            if line.is_empty() || line.starts_with('#') || items[0].trim().parse::<f64>().is_err() {
+                continue;
+            }
+            if items.len() != 2 {
+                return Err(format!(
+                    "rate schedule line {} should be seconds,rate",
+                    line
+                ));
+            }
+            let time = parse_number(items[0], "seconds")?;
+            if schedule.last().map(|s| s.0 >= time) == Some(true) {
+                return Err(format!("rate schedule time {} is not increasing", time));
+            }
+            schedule.push((time, parse_number(items[1], "rate")?));
+        }
+        if schedule.is_empty() {
+            return Err(format!("no rate found in schedule {}", file));
+        }
+        Ok(RateProfile::Schedule(schedule))
+    }
+
+    /// the target rate at the elapsed seconds since start, the last rate of
+    /// profile is kept after the profile ends.
+    pub fn rate(&self, elapsed: f64) -> f64 {
+        match self {
+            RateProfile::Constant(rate) => *rate,
+            RateProfile::Linear(from, to, seconds) => {
+                from + (to - from) * (elapsed / seconds).min(1.0)
+            }
+            RateProfile::Step(rates, seconds) => {
+                let index = (elapsed / seconds) as usize;
+                rates[index.min(rates.len() - 1)]
+            }
+            RateProfile::Sine(mean, amplitude, period) => {
+                (mean + amplitude * (2.0 * std::f64::consts::PI * elapsed / period).sin()).max(0.0)
+            }
+            RateProfile::Schedule(schedule) => schedule
+                .iter()
+                .rev()
+                .find(|(time, _)| *time <= elapsed)
+                .map(|(_, rate)| *rate)
+                .unwrap_or(0.0),
+        }
+    }
+
+    /// the number of queries should be sent between the elapsed seconds
+    pub fn expected(&self, from: f64, to: f64) -> f64 {
+        if to <= from {
+            return 0.0;
+        }
+        if let RateProfile::Constant(rate) = self {
+            return rate * (to - from);
+        }
+        let steps = ((to - from) / INTEGRATE_STEP)
+            .ceil()
+            .min(MAX_INTEGRATE_STEPS);
+        let step = (to - from) / steps;
+        (0..steps as usize)
+            .map(|i| self.rate(from + step * (i as f64 + 0.5)) * step)
+            .sum()
+    }
+
+    /// the highest rate of the profile
+    pub fn peak(&self) -> f64 {
+        match self {
+            RateProfile::Constant(rate) => *rate,
+            RateProfile::Linear(from, to, _) => from.max(*to),
+            RateProfile::Step(rates, _) => rates.iter().cloned().fold(0.0, f64::max),
+            RateProfile::Sine(mean, amplitude, _) => mean + amplitude,
+            RateProfile::Schedule(schedule) => {
+                schedule.iter().map(|(_, rate)| *rate).fold(0.0, f64::max)
+            }
+        }
+    }
+
+    /// the profile with all the rates multiplied by factor, each worker
+    /// sends its share of the rate.
+    pub fn scale(&self, factor: f64) -> RateProfile {
+        match self {
+            RateProfile::Constant(rate) => RateProfile::Constant(rate * factor),
+            RateProfile::Linear(from, to, seconds) => {
+                RateProfile::Linear(from * factor, to * factor, *seconds)
+            }
+            RateProfile::Step(rates, seconds) => {
+                RateProfile::Step(rates.iter().map(|r| r * factor).collect(), *seconds)
+            }
+            RateProfile::Sine(mean, amplitude, period) => {
+                RateProfile::Sine(mean * factor, amplitude * factor, *period)
+            }
+            RateProfile::Schedule(schedule) => {
+                RateProfile::Schedule(schedule.iter().map(|(t, r)| (*t, r * factor)).collect())
+            }
+        }
+    }
+}
+
+/// Arrival is how the queries are spaced in time at the target rate
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Arrival {
+    // the same interval between queries
+    #[default]
+    Constant,
+    // the exponential interval between queries
+    Poisson,
+    // the number of queries sent at once every period in seconds
+    Burst(u64, f64),
+}
+
+impl FromStr for Arrival {
+    type Err = String;
+    /// parse the arrival "constant", "poisson" or "burst:<queries>:<ms>"
+    fn from_str(arrival: &str) -> Result<Self, Self::Err> {
+        let items: Vec<&str> = arrival.split(':').collect();
+        match (items[0].to_lowercase().as_str(), items.len()) {
+            ("constant", 1) => Ok(Arrival::Constant),
+            ("poisson", 1) => Ok(Arrival::Poisson),
+            ("burst", 3) => {
+                let size = items[1].trim().parse::<u64>().unwrap_or(0);
+                let period = parse_number(items[2], "burst period")?;
+                if size == 0 || period == 0.0 {
+                    return Err(format!(
+                        "arrival {} burst size and period must be greater than 0",
+                        arrival
+                    ));
+                }
+                Ok(Arrival::Burst(size, period / 1000.0))
+            }
+            _ => Err(format!(
+                "arrival {} should be constant, poisson or burst:<queries>:<ms>",
+                arrival
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Arrival {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arrival::Constant => write!(f, "constant"),
+            Arrival::Poisson => write!(f, "poisson"),
+            Arrival::Burst(size, period) => {
+                write!(f, "burst of {} every {}ms", size, period * 1000.0)
+            }
+        }
+    }
+}
+
+impl Arrival {
+    /// the burst with the size divided by the workers, the first worker
+    /// sends the remainder.
+    pub fn split(&self, index: usize, workers: usize) -> Arrival {
+        match self {
+            Arrival::Burst(size, period) => {
+                let workers = workers as u64;
+                let share = size / workers + if index == 0 { size % workers } else { 0 };
+                Arrival::Burst(share, *period)
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+// the credit needed for the next query, it is exponential distributed for
+// poisson arrival so the queries are independent of each other
+fn next_threshold(arrival: &Arrival) -> f64 {
+    match arrival {
+        Arrival::Poisson => -(1.0 - rand::thread_rng().gen::<f64>()).ln(),
+        _ => 1.0,
+    }
+}
+
+/// Pacer decides when the next query is sent by the rate profile, the
+/// credit of sending grows with the current rate and a query is sent when
+/// the credit reaches the threshold, so the rate change is followed at once.
+/// The burst arrival gets all the credit of a period at its beginning.
+pub struct Pacer {
+    profile: RateProfile,
+    arrival: Arrival,
+    start: Instant,
+    last: f64,
+    credit: f64,
+    threshold: f64,
+    // the elapsed seconds of the next burst
+    next_burst: f64,
+}
+
+impl Pacer {
+    pub fn new(profile: RateProfile, arrival: Arrival) -> Pacer {
+        let profile = match arrival {
+            Arrival::Burst(size, period) => RateProfile::Constant(size as f64 / period),
+            _ => profile,
+        };
+        let threshold = next_threshold(&arrival);
+        // the first query is sent at once like the governor limiter
+        let credit = if profile.rate(0.0) > 0.0 {
+            threshold
+        } else {
+            0.0
+        };
+        Pacer {
+            profile,
+            arrival,
+            start: Instant::now(),
+            last: 0.0,
+            credit,
+            threshold,
+            next_burst: 0.0,
+        }
+    }
+
+    /// delay the first query by the phase of the interval between queries,
+    /// the workers sharing the rate start at different phases so the low
+    /// rate is kept even at the beginning.
+    pub fn with_phase(mut self, phase: f64) -> Pacer {
+        if self.credit > 0.0 && !matches!(self.arrival, Arrival::Burst(..)) {
+            self.credit = self.threshold * (1.0 - phase);
+        }
+        self
+    }
+
+    /// take a credit for sending a query, return the time to wait if the
+    /// credit is not enough.
+    pub fn check(&mut self) -> Option<Duration> {
+        let now = self.start.elapsed().as_secs_f64();
+        if let Arrival::Burst(size, period) = self.arrival {
+            // the queries not sent in the last burst are dropped
+            if now >= self.next_burst {
+                self.credit = size as f64;
+                self.next_burst = ((now / period).floor() + 1.0) * period;
+            }
+            if self.credit >= 1.0 {
+                self.credit -= 1.0;
+                return None;
+            }
+            return Some(Duration::from_secs_f64(
+                (self.next_burst - now).min(MAX_WAIT),
+            ));
+        }
+        let rate = self.profile.rate(now);
+        self.credit =
+            (self.credit + rate * (now - self.last)).min((rate * MAX_LAG).max(self.threshold));
+        self.last = now;
+        if self.credit >= self.threshold {
+            self.credit -= self.threshold;
+            self.threshold = next_threshold(&self.arrival);
+            return None;
+        }
+        let wait = if rate > 0.0 {
+            ((self.threshold - self.credit) / rate).min(MAX_WAIT)
+        } else {
+            MAX_WAIT
+        };
+        Some(Duration::from_secs_f64(wait))
+    }
+
+    /// the elapsed seconds when the next query is due at the current rate
+    pub fn next_due(&self) -> f64 {
+        if let Arrival::Burst(..) = self.arrival {
+            return self.next_burst;
+        }
+        let rate = self.profile.rate(self.last);
+        if rate > 0.0 {
+            self.last + (self.threshold - self.credit).max(0.0) / rate
+        } else {
+            self.last
+        }
+    }
+
+    pub fn profile(&self) -> &RateProfile {
+        &self.profile
+    }
+
+    /// change the rate from now on, the credit is kept so the interval
+    /// between queries is not reset.
+    pub fn set_profile(&mut self, profile: RateProfile) {
+        if let Arrival::Burst(..) = self.arrival {
+            return;
+        }
+        self.profile = profile;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::rate::{Arrival, Pacer, RateProfile};
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_profile() {
+        let linear: RateProfile = "linear:100:300:10".parse().unwrap();
+        assert_eq!(linear, RateProfile::Linear(100.0, 300.0, 10.0));
+        assert_eq!(linear.rate(5.0), 200.0);
+        assert_eq!(linear.rate(20.0), 300.0);
+        assert!((linear.expected(0.0, 10.0) - 2000.0).abs() < 1e-6);
+
+        let step: RateProfile = "step:100,200,400:30".parse().unwrap();
+        assert_eq!(step.rate(0.0), 100.0);
+        assert_eq!(step.rate(31.0), 200.0);
+        assert_eq!(step.rate(1000.0), 400.0);
+        assert!((step.scale(0.5).expected(0.0, 60.0) - 4500.0).abs() < 1e-6);
+
+        let sine: RateProfile = "sine:100:50:60".parse().unwrap();
+        assert!((sine.rate(15.0) - 150.0).abs() < 1e-6);
+        assert!((sine.expected(0.0, 60.0) - 6000.0).abs() < 1e-6);
+
+        let schedule = RateProfile::Schedule(vec![(0.0, 10.0), (5.0, 50.0)]);
+        assert_eq!(schedule.rate(4.9), 10.0);
+        assert_eq!(schedule.rate(5.0), 50.0);
+
+        assert_eq!(step.peak(), 400.0);
+        assert_eq!(sine.peak(), 150.0);
+        assert_eq!(schedule.peak(), 50.0);
+
+        assert!("linear:100:300".parse::<RateProfile>().is_err());
+        assert!("step:100,-1:10".parse::<RateProfile>().is_err());
+        assert!("sine:100:50:0".parse::<RateProfile>().is_err());
+        assert!("poisson:100".parse::<RateProfile>().is_err());
+    }
+
+    #[test]
+    fn test_pacer() {
+        let mut pacer = Pacer::new(RateProfile::Constant(10.0), Arrival::Constant);
+        assert_eq!(pacer.check(), None);
+        let wait = pacer.check().unwrap();
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+
+        let mut pacer = Pacer::new(RateProfile::Constant(0.0), Arrival::Constant);
+        assert_eq!(pacer.check(), Some(Duration::from_millis(100)));
+
+        // the second of two workers sharing 1 qps starts one second later
+        let mut pacer = Pacer::new(RateProfile::Constant(0.5), Arrival::Constant).with_phase(0.5);
+        let wait = pacer.check().unwrap();
+        assert!(wait > Duration::from_millis(99) && wait <= Duration::from_millis(100));
+
+        // the rate of burst is set by its size and period
+        let mut pacer = Pacer::new(RateProfile::Constant(1.0), Arrival::Burst(3, 60.0));
+        assert_eq!(pacer.profile(), &RateProfile::Constant(0.05));
+        assert_eq!((0..3).filter(|_| pacer.check().is_none()).count(), 3);
+        assert_eq!(pacer.check(), Some(Duration::from_millis(100)));
+
+        let mut pacer = Pacer::new(RateProfile::Constant(1000.0), Arrival::Poisson);
+        assert_eq!(pacer.check(), None);
+    }
+
+    #[test]
+    fn test_arrival() {
+        assert_eq!("Poisson".parse::<Arrival>(), Ok(Arrival::Poisson));
+        let burst: Arrival = "burst:10:250".parse().unwrap();
+        assert_eq!(burst, Arrival::Burst(10, 0.25));
+        assert_eq!(burst.to_string(), "burst of 10 every 250ms");
+        assert_eq!(burst.split(0, 3), Arrival::Burst(4, 0.25));
+        assert_eq!(burst.split(2, 3), Arrival::Burst(3, 0.25));
+        assert!("burst:0:100".parse::<Arrival>().is_err());
+        assert!("burst:10".parse::<Arrival>().is_err());
+        assert!("uniform".parse::<Arrival>().is_err());
+    }
+}
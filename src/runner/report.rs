@@ -1,26 +1,398 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::Write;
 use std::ops::Add;
 use trust_dns_client::op::ResponseCode;
 use trust_dns_client::op::{Header, Message};
+use trust_dns_client::proto::rr::rdata::opt::EdnsCode;
 use trust_dns_client::rr::RecordType;
 // use crate::histogram::{HistogramReport};
+use crate::runner::ede::info_code_name;
+use crate::runner::fuzz::{Mutation, MutationResult};
 use crate::runner::histogram::HistogramReport;
 use crate::runner::runner::merge_map;
+use crate::utils::dnssec::DnssecStatus;
+use crate::utils::tsig::TsigStatus;
+use crate::workers::session::{CaseStatus, CookieStatus, Inspection};
+
+/// TransferStatus is the summary of the finished zone transfers, the failed
+/// transfers are only counted by the response code or reason.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct TransferStatus {
+    pub transfer_total: u64,
+    pub failed: BTreeMap<String, u64>,
+    pub record_total: u64,
+    pub byte_total: u64,
+    pub message_total: u64,
+    pub min_duration: f64,
+    pub max_duration: f64,
+    pub mean_duration: f64,
+}
+
+impl Add<TransferStatus> for TransferStatus {
+    type Output = TransferStatus;
+
+    fn add(self, rhs: TransferStatus) -> Self::Output {
+        let transfer_total = self.transfer_total + rhs.transfer_total;
+        let mut failed = self.failed.clone();
+        for (reason, count) in rhs.failed.iter() {
+            *failed.entry(reason.clone()).or_insert(0) += count;
+        }
+        if self.transfer_total == 0 || rhs.transfer_total == 0 {
+            let status = if self.transfer_total == 0 { rhs } else { self };
+            return TransferStatus { failed, ..status };
+        }
+        TransferStatus {
+            transfer_total,
+            failed,
+            record_total: self.record_total + rhs.record_total,
+            byte_total: self.byte_total + rhs.byte_total,
+            message_total: self.message_total + rhs.message_total,
+            min_duration: self.min_duration.min(rhs.min_duration),
+            max_duration: self.max_duration.max(rhs.max_duration),
+            mean_duration: (self.mean_duration * self.transfer_total as f64
+                + rhs.mean_duration * rhs.transfer_total as f64)
+                / transfer_total as f64,
+        }
+    }
+}
+
+impl TransferStatus {
+    /// add a finished transfer with its records, bytes, messages and duration
+    pub fn update(&mut self, records: u64, bytes: u64, messages: u64, duration: f64) {
+        *self = self.clone()
+            + TransferStatus {
+                transfer_total: 1,
+                failed: BTreeMap::new(),
+                record_total: records,
+                byte_total: bytes,
+                message_total: messages,
+                min_duration: duration,
+                max_duration: duration,
+                mean_duration: duration,
+            };
+    }
+
+    /// add a failed transfer with the response code or reason
+    pub fn fail(&mut self, reason: &str) {
+        *self.failed.entry(reason.to_string()).or_insert(0) += 1;
+    }
+}
+
+// the number of mismatch samples kept for report
+const MAX_MISMATCH_SAMPLES: usize = 10;
+// the number of distinct extended dns error texts kept for report
+const MAX_EXTENDED_ERROR_TEXTS: usize = 100;
+
+/// ExpectationStatus is the result of checking the responses against the
+/// expectations in query file.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ExpectationStatus {
+    pub checked_total: u64,
+    pub mismatch_total: u64,
+    pub samples: Vec<String>,
+}
+
+impl Add<ExpectationStatus> for ExpectationStatus {
+    type Output = ExpectationStatus;
+
+    fn add(mut self, rhs: ExpectationStatus) -> Self::Output {
+        for sample in rhs.samples {
+            add_sample(&mut self.samples, sample);
+        }
+        ExpectationStatus {
+            checked_total: self.checked_total + rhs.checked_total,
+            mismatch_total: self.mismatch_total + rhs.mismatch_total,
+            samples: self.samples,
+        }
+    }
+}
+
+impl ExpectationStatus {
+    /// add a checked response with the difference if it's not expected
+    pub fn update(&mut self, diff: Option<String>) {
+        self.checked_total += 1;
+        if let Some(diff) = diff {
+            self.mismatch_total += 1;
+            add_sample(&mut self.samples, diff);
+        }
+    }
+}
+
+fn add_sample(samples: &mut Vec<String>, sample: String) {
+    if samples.len() < MAX_MISMATCH_SAMPLES && !samples.contains(&sample) {
+        samples.push(sample);
+    }
+}
+
+/// LatencyStatus is the latency summary of one server in compare mode
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct LatencyStatus {
+    pub response_total: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Add<LatencyStatus> for LatencyStatus {
+    type Output = LatencyStatus;
+
+    fn add(self, rhs: LatencyStatus) -> Self::Output {
+        let response_total = self.response_total + rhs.response_total;
+        if self.response_total == 0 || rhs.response_total == 0 {
+            return if self.response_total == 0 { rhs } else { self };
+        }
+        LatencyStatus {
+            response_total,
+            min: self.min.min(rhs.min),
+            max: self.max.max(rhs.max),
+            mean: (self.mean * self.response_total as f64 + rhs.mean * rhs.response_total as f64)
+                / response_total as f64,
+        }
+    }
+}
+
+impl LatencyStatus {
+    pub fn update(&mut self, duration: f64) {
+        *self = self.clone()
+            + LatencyStatus {
+                response_total: 1,
+                min: duration,
+                max: duration,
+                mean: duration,
+            };
+    }
+}
+
+// the lower bounds of the distribution buckets
+const SIZE_BUCKETS: [u64; 8] = [0, 128, 256, 512, 1024, 1232, 1500, 4096];
+const COUNT_BUCKETS: [u64; 8] = [0, 1, 2, 3, 4, 5, 10, 20];
+const TTL_BUCKETS: [u64; 8] = [0, 1, 10, 60, 300, 900, 3600, 86400];
+
+/// DistributionStatus counts the values in buckets keyed by the lower bound
+/// of bucket, the min, max and mean of the values are kept too.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct DistributionStatus {
+    pub total: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    pub buckets: BTreeMap<u64, u64>,
+}
+
+impl Add<DistributionStatus> for DistributionStatus {
+    type Output = DistributionStatus;
+
+    fn add(mut self, rhs: DistributionStatus) -> Self::Output {
+        let total = self.total + rhs.total;
+        if self.total == 0 || rhs.total == 0 {
+            return if self.total == 0 { rhs } else { self };
+        }
+        for (bucket, count) in rhs.buckets {
+            *self.buckets.entry(bucket).or_insert(0) += count;
+        }
+        DistributionStatus {
+            total,
+            min: self.min.min(rhs.min),
+            max: self.max.max(rhs.max),
+            mean: (self.mean * self.total as f64 + rhs.mean * rhs.total as f64) / total as f64,
+            buckets: self.buckets,
+        }
+    }
+}
+
+impl DistributionStatus {
+    /// add the value to the last bucket whose lower bound is not greater
+    /// than it
+    pub fn update(&mut self, value: u64, buckets: &[u64]) {
+        let bucket = buckets
+            .iter()
+            .rev()
+            .find(|bound| **bound <= value)
+            .copied()
+            .unwrap_or(0);
+        let mut status = DistributionStatus {
+            total: 1,
+            min: value,
+            max: value,
+            mean: value as f64,
+            buckets: BTreeMap::new(),
+        };
+        status.buckets.insert(bucket, 1);
+        *self = std::mem::take(self) + status;
+    }
+}
+
+/// ResponseStatus is the distribution of the parsed responses, which shows
+/// the change of cache behavior (TTL decay), truncation and response size.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ResponseStatus {
+    pub size: DistributionStatus,
+    pub answer_count: DistributionStatus,
+    // the minimum TTL of answers, or authority records if no answer
+    pub min_ttl: DistributionStatus,
+    // the number of responses with the header flag set
+    pub flag: BTreeMap<String, u64>,
+}
+
+impl Add<ResponseStatus> for ResponseStatus {
+    type Output = ResponseStatus;
+
+    fn add(mut self, rhs: ResponseStatus) -> Self::Output {
+        for (flag, count) in rhs.flag {
+            *self.flag.entry(flag).or_insert(0) += count;
+        }
+        ResponseStatus {
+            size: self.size + rhs.size,
+            answer_count: self.answer_count + rhs.answer_count,
+            min_ttl: self.min_ttl + rhs.min_ttl,
+            flag: self.flag,
+        }
+    }
+}
+
+impl ResponseStatus {
+    pub fn update(&mut self, message: &Message) {
+        self.answer_count
+            .update(message.answers().len() as u64, &COUNT_BUCKETS);
+        let records = if message.answers().is_empty() {
+            message.name_servers()
+        } else {
+            message.answers()
+        };
+        if let Some(ttl) = records.iter().map(|r| r.ttl()).min() {
+            self.min_ttl.update(ttl as u64, &TTL_BUCKETS);
+        }
+        for (flag, set) in [
+            ("AA", message.authoritative()),
+            ("TC", message.truncated()),
+            ("RA", message.recursion_available()),
+            ("AD", message.authentic_data()),
+        ]
+        .iter()
+        {
+            if *set {
+                *self.flag.entry(flag.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn update_size(&mut self, size: usize) {
+        self.size.update(size as u64, &SIZE_BUCKETS);
+    }
+}
+
+/// RateStatus is the target and achieved query rate of a period, which is
+/// the interval or the whole run.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct RateStatus {
+    pub target: f64,
+    pub achieved: f64,
+}
+
+impl Add<RateStatus> for RateStatus {
+    type Output = RateStatus;
+
+    fn add(self, rhs: RateStatus) -> Self::Output {
+        RateStatus {
+            target: self.target + rhs.target,
+            achieved: self.achieved + rhs.achieved,
+        }
+    }
+}
+
+impl RateStatus {
+    /// the percent of achieved rate above (positive) or below the target
+    pub fn deviation(&self) -> f64 {
+        if self.target > 0.0 {
+            (self.achieved - self.target) * 100.0 / self.target
+        } else {
+            0.0
+        }
+    }
+}
+
+/// CompareStatus is the result of comparing the responses of two servers,
+/// the mismatches are counted by category.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct CompareStatus {
+    pub compared_total: u64,
+    pub mismatch_total: u64,
+    pub category: BTreeMap<String, u64>,
+    pub samples: Vec<String>,
+    pub primary: LatencyStatus,
+    pub secondary: LatencyStatus,
+}
+
+impl Add<CompareStatus> for CompareStatus {
+    type Output = CompareStatus;
+
+    fn add(mut self, rhs: CompareStatus) -> Self::Output {
+        for sample in rhs.samples {
+            add_sample(&mut self.samples, sample);
+        }
+        for (category, count) in rhs.category {
+            *self.category.entry(category).or_insert(0) += count;
+        }
+        CompareStatus {
+            compared_total: self.compared_total + rhs.compared_total,
+            mismatch_total: self.mismatch_total + rhs.mismatch_total,
+            category: self.category,
+            samples: self.samples,
+            primary: self.primary + rhs.primary,
+            secondary: self.secondary + rhs.secondary,
+        }
+    }
+}
+
+impl CompareStatus {
+    /// add the differences of a query, the query is formatted in samples
+    pub fn update(&mut self, query: &str, differences: &[(&str, String)]) {
+        self.compared_total += 1;
+        if differences.is_empty() {
+            return;
+        }
+        self.mismatch_total += 1;
+        for (category, difference) in differences {
+            *self.category.entry(category.to_string()).or_insert(0) += 1;
+            add_sample(
+                &mut self.samples,
+                format!("{}: {} {}", query, category, difference),
+            );
+        }
+    }
+}
 
 #[derive(Default, Clone, Debug)]
 pub struct StatusStore {
     query_total: u64,
     receive_total: u64,
+    // the responses match no outstanding query by id or question
+    unexpected_total: u64,
+    // the queries not answered in the timeout
+    timeout_total: u64,
     send_duration: Option<std::time::Duration>,
     last_update: Option<std::time::SystemTime>,
     query_type: HashMap<u16, u64>,
     answer_type: HashMap<u16, u64>,
     authority_type: HashMap<u16, u64>,
     additional_type: HashMap<u16, u64>,
-    reply_code: HashMap<u8, u64>,
+    reply_code: HashMap<u16, u64>,
+    // the extended dns errors by info code and by "code: extra text"
+    extended_error: HashMap<u16, u64>,
+    extended_error_text: HashMap<String, u64>,
+    cookie: HashMap<CookieStatus, u64>,
+    tsig: HashMap<TsigStatus, u64>,
+    case: HashMap<CaseStatus, u64>,
+    dnssec: HashMap<DnssecStatus, u64>,
+    edns_option: HashMap<u16, u64>,
+    pub transfer: TransferStatus,
+    pub expectation: ExpectationStatus,
+    pub compare: CompareStatus,
+    pub response: ResponseStatus,
+    pub rate: RateStatus,
+    mutation: HashMap<(Mutation, MutationResult), u64>,
     report: Option<HistogramReport>,
 }
 
@@ -31,6 +403,8 @@ impl Add<StatusStore> for StatusStore {
         Self {
             query_total: self.query_total + rhs.query_total,
             receive_total: self.receive_total + rhs.receive_total,
+            unexpected_total: self.unexpected_total + rhs.unexpected_total,
+            timeout_total: self.timeout_total + rhs.timeout_total,
             send_duration: {
                 match (self.send_duration, rhs.send_duration) {
                     (Some(v1), Some(v2)) => {
@@ -64,6 +438,19 @@ impl Add<StatusStore> for StatusStore {
             authority_type: merge_map(&self.authority_type, &rhs.authority_type),
             additional_type: merge_map(&self.additional_type, &rhs.additional_type),
             reply_code: merge_map(&self.reply_code, &rhs.reply_code),
+            extended_error: merge_map(&self.extended_error, &rhs.extended_error),
+            extended_error_text: merge_map(&self.extended_error_text, &rhs.extended_error_text),
+            cookie: merge_map(&self.cookie, &rhs.cookie),
+            tsig: merge_map(&self.tsig, &rhs.tsig),
+            case: merge_map(&self.case, &rhs.case),
+            dnssec: merge_map(&self.dnssec, &rhs.dnssec),
+            edns_option: merge_map(&self.edns_option, &rhs.edns_option),
+            transfer: self.transfer + rhs.transfer,
+            expectation: self.expectation + rhs.expectation,
+            compare: self.compare + rhs.compare,
+            response: self.response + rhs.response,
+            rate: self.rate + rhs.rate,
+            mutation: merge_map(&self.mutation, &rhs.mutation),
             report: {
                 match (&self.report, &rhs.report) {
                     (Some(v1), Some(v2)) => Some(v1.clone() + v2.clone()),
@@ -81,6 +468,8 @@ impl StatusStore {
         StatusStore {
             query_total: 0,
             receive_total: 0,
+            unexpected_total: 0,
+            timeout_total: 0,
             send_duration: None,
             last_update: None,
             query_type: Default::default(),
@@ -88,6 +477,19 @@ impl StatusStore {
             authority_type: Default::default(),
             additional_type: Default::default(),
             reply_code: Default::default(),
+            extended_error: Default::default(),
+            extended_error_text: Default::default(),
+            cookie: Default::default(),
+            tsig: Default::default(),
+            case: Default::default(),
+            dnssec: Default::default(),
+            edns_option: Default::default(),
+            transfer: Default::default(),
+            expectation: Default::default(),
+            compare: Default::default(),
+            response: Default::default(),
+            rate: Default::default(),
+            mutation: Default::default(),
             report: None,
         }
     }
@@ -99,6 +501,8 @@ impl StatusStore {
         StatusStore {
             query_total: query_total,
             receive_total: 0,
+            unexpected_total: 0,
+            timeout_total: 0,
             send_duration: None,
             last_update: Some(std::time::SystemTime::now()),
             query_type: query_status.clone(),
@@ -106,6 +510,19 @@ impl StatusStore {
             authority_type: Default::default(),
             additional_type: Default::default(),
             reply_code: Default::default(),
+            extended_error: Default::default(),
+            extended_error_text: Default::default(),
+            cookie: Default::default(),
+            tsig: Default::default(),
+            case: Default::default(),
+            dnssec: Default::default(),
+            edns_option: Default::default(),
+            transfer: Default::default(),
+            expectation: Default::default(),
+            compare: Default::default(),
+            response: Default::default(),
+            rate: Default::default(),
+            mutation: Default::default(),
             report: None,
         }
     }
@@ -115,6 +532,28 @@ impl StatusStore {
     pub fn set_receive_total(&mut self, total: u64) {
         self.receive_total = total;
     }
+    pub fn update_unexpected(&mut self) {
+        self.unexpected_total += 1;
+    }
+    pub fn update_timeout(&mut self) {
+        self.timeout_total += 1;
+    }
+    pub fn query_total(&self) -> u64 {
+        self.query_total
+    }
+    pub fn receive_total(&self) -> u64 {
+        self.receive_total
+    }
+    pub fn timeout_total(&self) -> u64 {
+        self.timeout_total
+    }
+    /// the number of responses with the response code
+    pub fn reply_code_total(&self, code: u16) -> u64 {
+        self.reply_code.get(&code).cloned().unwrap_or(0)
+    }
+    pub fn histogram_report(&self) -> Option<&HistogramReport> {
+        self.report.as_ref()
+    }
     pub fn set_send_duration(&mut self, duration: std::time::Duration) {
         self.send_duration = Some(duration);
     }
@@ -133,7 +572,7 @@ impl StatusStore {
         self.report = report;
     }
     pub fn update_response_from_header(&mut self, header: &Header) {
-        let r_code = header.response_code();
+        let r_code = u16::from(header.response_code());
         let count = self.reply_code.entry(r_code).or_insert(0);
         *count += 1;
         self.last_update = Some(std::time::SystemTime::now());
@@ -161,17 +600,78 @@ impl StatusStore {
             *count += 1;
         }
 
-        let r_code = message.header().response_code();
+        if let Some(edns) = message.edns() {
+            for code in edns.options().as_ref().keys() {
+                let count = self.edns_option.entry(u16::from(*code)).or_insert(0);
+                *count += 1;
+            }
+        }
+
+        self.response.update(message);
+
+        let r_code = u16::from(message.response_code());
         let count = self.reply_code.entry(r_code).or_insert(0);
         *count += 1;
         self.last_update = Some(std::time::SystemTime::now());
     }
+    pub fn update_mutation(&mut self, mutation: Mutation, result: MutationResult) {
+        let count = self.mutation.entry((mutation, result)).or_insert(0);
+        *count += 1;
+    }
+    pub fn update_inspection(&mut self, inspection: &Inspection) {
+        if inspection.size > 0 {
+            self.response.update_size(inspection.size);
+        }
+        if let Some(status) = inspection.cookie {
+            let count = self.cookie.entry(status).or_insert(0);
+            *count += 1;
+        }
+        if let Some(status) = inspection.tsig {
+            let count = self.tsig.entry(status).or_insert(0);
+            *count += 1;
+        }
+        if let Some(status) = inspection.case {
+            let count = self.case.entry(status).or_insert(0);
+            *count += 1;
+        }
+        if let Some(status) = inspection.dnssec {
+            let count = self.dnssec.entry(status).or_insert(0);
+            *count += 1;
+        }
+        for (code, text) in inspection.extended_error.iter() {
+            let count = self.extended_error.entry(*code).or_insert(0);
+            *count += 1;
+            if text.is_empty() {
+                continue;
+            }
+            let text = format!("{}: {}", code, text);
+            if self.extended_error_text.len() < MAX_EXTENDED_ERROR_TEXTS
+                || self.extended_error_text.contains_key(&text)
+            {
+                let count = self.extended_error_text.entry(text).or_insert(0);
+                *count += 1;
+            }
+        }
+        // move the response from its rcode to the TSIG error
+        if let Some(code) = inspection.tsig_response_code() {
+            if let Some(count) = self.reply_code.get_mut(&inspection.response_code) {
+                *count -= 1;
+                if *count == 0 {
+                    self.reply_code.remove(&inspection.response_code);
+                }
+            }
+            let count = self.reply_code.entry(code).or_insert(0);
+            *count += 1;
+        }
+    }
 }
 
 pub struct RunnerReport {
     producer_report: Option<StatusStore>,
     consumer_report: Option<StatusStore>,
     histogram: Option<HistogramReport>,
+    // the reason why the run is aborted by the safeguard
+    abort: Option<String>,
 }
 
 impl RunnerReport {
@@ -180,8 +680,12 @@ impl RunnerReport {
             producer_report: None,
             consumer_report: None,
             histogram: None,
+            abort: None,
         }
     }
+    pub fn set_abort(&mut self, reason: String) {
+        self.abort = Some(reason);
+    }
     pub fn set_producer_report(&mut self, store: StatusStore) {
         self.producer_report = Some(store);
     }
@@ -218,10 +722,20 @@ pub enum ReportType {
 
 struct BasicStats {
     response_code: Vec<(ResponseCode, u64)>,
+    extended_error: Vec<(u16, u64)>,
+    extended_error_text: Vec<(String, u64)>,
     duration: std::time::Duration,
     query_total: u64,
     response_total: u64,
+    unexpected_total: u64,
+    timeout_total: u64,
     qps: u64,
+    target_qps: f64,
+    achieved_qps: f64,
+    // the percent of achieved rate above or below the target
+    rate_deviation: f64,
+    // the reason why the run is aborted
+    aborted: Option<String>,
     query_rate: f64,
     min_lantency: f64,
     max_lantency: f64,
@@ -235,10 +749,20 @@ struct BasicStats {
 #[derive(Serialize, Deserialize, Debug)]
 struct BasicStatsSerializable {
     response_code: Vec<ItemKeyValue>,
+    extended_error: Vec<ItemKeyValue>,
+    extended_error_text: Vec<ItemKeyValue>,
     duration: std::time::Duration,
     query_total: u64,
     response_total: u64,
+    unexpected_total: u64,
+    timeout_total: u64,
     qps: u64,
+    target_qps: f64,
+    achieved_qps: f64,
+    // the percent of achieved rate above or below the target
+    rate_deviation: f64,
+    // the reason why the run is aborted
+    aborted: Option<String>,
     query_rate: f64,
     min_lantency: f64,
     max_lantency: f64,
@@ -248,7 +772,7 @@ struct BasicStatsSerializable {
     p90: f64,
     p50: f64,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct ItemKeyValue {
     key: String,
     value: u64,
@@ -271,10 +795,32 @@ impl BasicStats {
                     value: a.1,
                 })
                 .collect(),
+            extended_error: self
+                .extended_error
+                .iter()
+                .map(|a| ItemKeyValue {
+                    key: info_code_name(a.0),
+                    value: a.1,
+                })
+                .collect(),
+            extended_error_text: self
+                .extended_error_text
+                .iter()
+                .map(|a| ItemKeyValue {
+                    key: a.0.clone(),
+                    value: a.1,
+                })
+                .collect(),
             duration: self.duration,
             query_total: self.query_total,
             response_total: self.response_total,
+            unexpected_total: self.unexpected_total,
+            timeout_total: self.timeout_total,
             qps: self.qps,
+            target_qps: self.target_qps,
+            achieved_qps: self.achieved_qps,
+            rate_deviation: self.rate_deviation,
+            aborted: self.aborted.clone(),
             query_rate: self.query_rate,
             min_lantency: self.min_lantency,
             max_lantency: self.max_lantency,
@@ -289,6 +835,25 @@ impl BasicStats {
         let response_code =
             format_code_result(&report.consumer_report.as_ref().unwrap().reply_code);
 
+        let mut extended_error: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .extended_error
+            .iter()
+            .map(|a| (*a.0, *a.1))
+            .collect();
+        extended_error.sort_by_key(|a| a.0);
+        let mut extended_error_text: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .extended_error_text
+            .iter()
+            .map(|a| (a.0.clone(), *a.1))
+            .collect();
+        extended_error_text.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
         let duration = report
             .producer_report
             .as_ref()
@@ -298,17 +863,28 @@ impl BasicStats {
         let qps = (report.producer_report.as_ref().unwrap().query_total as f64
             / duration.as_secs_f64()) as u64;
         let query_total = report.producer_report.as_ref().unwrap().query_total;
+        let rate = report.producer_report.as_ref().unwrap().rate.clone();
         let response_total = report.consumer_report.as_ref().unwrap().receive_total;
+        let unexpected_total = report.consumer_report.as_ref().unwrap().unexpected_total;
+        let timeout_total = report.consumer_report.as_ref().unwrap().timeout_total;
         let query_rate = report.consumer_report.as_ref().unwrap().receive_total as f64 * 100.0
             / report.producer_report.as_ref().unwrap().query_total as f64;
 
         if report.histogram.is_none() {
             BasicStats {
                 response_code,
+                extended_error,
+                extended_error_text,
                 duration,
                 qps,
+                target_qps: rate.target,
+                achieved_qps: rate.achieved,
+                rate_deviation: rate.deviation(),
+                aborted: report.abort.clone(),
                 query_total,
                 response_total,
+                unexpected_total,
+                timeout_total,
                 query_rate,
                 min_lantency: 0.0,
                 max_lantency: 0.0,
@@ -322,10 +898,18 @@ impl BasicStats {
             let histogram = report.histogram.as_ref().unwrap();
             BasicStats {
                 response_code,
+                extended_error,
+                extended_error_text,
                 duration,
                 qps,
+                target_qps: rate.target,
+                achieved_qps: rate.achieved,
+                rate_deviation: rate.deviation(),
+                aborted: report.abort.clone(),
                 query_total,
                 response_total,
+                unexpected_total,
+                timeout_total,
                 query_rate,
                 min_lantency: histogram.min,
                 max_lantency: histogram.max,
@@ -345,6 +929,22 @@ struct ExtensionStats {
     answer_result: Vec<(RecordType, u64)>,
     additional_result: Vec<(RecordType, u64)>,
     authority_result: Vec<(RecordType, u64)>,
+    cookie: Vec<(CookieStatus, u64)>,
+    tsig: Vec<(TsigStatus, u64)>,
+    case: Vec<(CaseStatus, u64)>,
+    dnssec: Vec<(DnssecStatus, u64)>,
+    edns_option: Vec<(EdnsCode, u64)>,
+    transfer: TransferStatus,
+    expectation: ExpectationStatus,
+    compare: CompareStatus,
+    response: ResponseStatus,
+    mutation: Vec<MutationStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MutationStats {
+    mutation: String,
+    result: Vec<ItemKeyValue>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -354,6 +954,16 @@ struct ExtensionStatsSerializable {
     answer_result: Vec<ItemKeyValue>,
     additional_result: Vec<ItemKeyValue>,
     authority_result: Vec<ItemKeyValue>,
+    cookie: Vec<ItemKeyValue>,
+    tsig: Vec<ItemKeyValue>,
+    case: Vec<ItemKeyValue>,
+    dnssec: Vec<ItemKeyValue>,
+    edns_option: Vec<ItemKeyValue>,
+    transfer: TransferStatus,
+    expectation: ExpectationStatus,
+    compare: CompareStatus,
+    response: ResponseStatus,
+    mutation: Vec<MutationStats>,
 }
 
 impl ExtensionStats {
@@ -400,6 +1010,51 @@ impl ExtensionStats {
                     value: a.1,
                 })
                 .collect(),
+            cookie: self
+                .cookie
+                .iter()
+                .map(|a| ItemKeyValue {
+                    key: a.0.to_string(),
+                    value: a.1,
+                })
+                .collect(),
+            tsig: self
+                .tsig
+                .iter()
+                .map(|a| ItemKeyValue {
+                    key: a.0.to_string(),
+                    value: a.1,
+                })
+                .collect(),
+            case: self
+                .case
+                .iter()
+                .map(|a| ItemKeyValue {
+                    key: a.0.to_string(),
+                    value: a.1,
+                })
+                .collect(),
+            dnssec: self
+                .dnssec
+                .iter()
+                .map(|a| ItemKeyValue {
+                    key: a.0.to_string(),
+                    value: a.1,
+                })
+                .collect(),
+            edns_option: self
+                .edns_option
+                .iter()
+                .map(|a| ItemKeyValue {
+                    key: format!("{:?}", a.0),
+                    value: a.1,
+                })
+                .collect(),
+            transfer: self.transfer.clone(),
+            expectation: self.expectation.clone(),
+            compare: self.compare.clone(),
+            response: self.response.clone(),
+            mutation: self.mutation.clone(),
         }
     }
     fn new(report: &RunnerReport) -> ExtensionStats {
@@ -409,7 +1064,7 @@ impl ExtensionStats {
             .unwrap()
             .query_type
             .iter()
-            .map(|a| (RecordType::from(*a.0), *a.1))
+            .map(|a| (record_type(*a.0), *a.1))
             .collect();
         query_type.sort_by_key(|a| a.0);
 
@@ -424,7 +1079,7 @@ impl ExtensionStats {
         let response_type: Vec<_> = response_type_map
             .iter()
             .map(|a| {
-                let query_type = RecordType::from(*a.0);
+                let query_type = record_type(*a.0);
                 let rate: f64 = {
                     if let Some(query) =
                         report.producer_report.as_ref().unwrap().query_type.get(a.0)
@@ -445,12 +1100,96 @@ impl ExtensionStats {
 
         let authority_result =
             format_result(&report.consumer_report.as_ref().unwrap().authority_type);
+
+        let mut cookie: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .cookie
+            .iter()
+            .map(|a| (*a.0, *a.1))
+            .collect();
+        cookie.sort_by_key(|a| a.0.to_string());
+
+        let mut tsig: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .tsig
+            .iter()
+            .map(|a| (*a.0, *a.1))
+            .collect();
+        tsig.sort_by_key(|a| a.0.to_string());
+
+        let mut case: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .case
+            .iter()
+            .map(|a| (*a.0, *a.1))
+            .collect();
+        case.sort_by_key(|a| a.0.to_string());
+
+        let mut dnssec: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .dnssec
+            .iter()
+            .map(|a| (*a.0, *a.1))
+            .collect();
+        dnssec.sort_by_key(|a| a.0.to_string());
+
+        let mut edns_option_map: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .edns_option
+            .iter()
+            .collect();
+        edns_option_map.sort_by_key(|a| a.0);
+        let edns_option = edns_option_map
+            .iter()
+            .map(|a| (EdnsCode::from(*a.0), *a.1))
+            .collect();
+        let mut mutation_map: Vec<_> = report
+            .consumer_report
+            .as_ref()
+            .unwrap()
+            .mutation
+            .iter()
+            .collect();
+        mutation_map.sort_by_key(|a| a.0);
+        let mut mutation: Vec<MutationStats> = vec![];
+        for ((class, result), count) in mutation_map {
+            if mutation.last().map(|m| m.mutation != class.to_string()) != Some(false) {
+                mutation.push(MutationStats {
+                    mutation: class.to_string(),
+                    result: vec![],
+                });
+            }
+            mutation.last_mut().unwrap().result.push(ItemKeyValue {
+                key: result.to_string(),
+                value: *count,
+            });
+        }
         ExtensionStats {
             query_type,
             response_type,
             answer_result,
             additional_result,
             authority_result,
+            cookie,
+            tsig,
+            case,
+            dnssec,
+            edns_option,
+            transfer: report.consumer_report.as_ref().unwrap().transfer.clone(),
+            expectation: report.consumer_report.as_ref().unwrap().expectation.clone(),
+            compare: report.consumer_report.as_ref().unwrap().compare.clone(),
+            response: report.consumer_report.as_ref().unwrap().response.clone(),
+            mutation,
         }
     }
 }
@@ -487,13 +1226,27 @@ impl ReportType {
             .collect::<Vec<String>>()
             .join(",");
 
-        let out_put = format!(
+        // the extended dns errors are shown below the response code
+        let mut extended_error = String::new();
+        if !basic_info.extended_error.is_empty() {
+            let codes: Vec<_> = basic_info
+                .extended_error
+                .iter()
+                .map(|a| format!("{}={}", a.key, a.value))
+                .collect();
+            extended_error += &format!("\n  Extended Error: {}", codes.join(","));
+            for text in basic_info.extended_error_text.iter() {
+                extended_error += &format!("\n                  {} ({})", text.key, text.value);
+            }
+        }
+
+        let mut out_put = format!(
             "------------   Report   --------------
       Total Cost: {:?}
      Total Query: {}
         Question: {}
   Total Response: {}
-   Response Code: {}
+   Response Code: {}{}
     Success Rate: {:.2}%
      Average QPS: {:.0}
      Min Latency: {:?}
@@ -508,6 +1261,7 @@ impl ReportType {
             query.join(","),
             basic_info.response_total,
             response_code,
+            extended_error,
             basic_info.query_rate,
             basic_info.qps,
             std::time::Duration::from_secs_f64(basic_info.min_lantency),
@@ -518,6 +1272,145 @@ impl ReportType {
             std::time::Duration::from_secs_f64(basic_info.p90),
             std::time::Duration::from_secs_f64(basic_info.p50),
         );
+        if basic_info.target_qps > 0.0 {
+            out_put += &format!(
+                "\n      Target QPS: {:.0}\n    Achieved QPS: {:.0}\n  Rate Deviation: {:+.2}%",
+                basic_info.target_qps, basic_info.achieved_qps, basic_info.rate_deviation
+            );
+        }
+        if basic_info.unexpected_total > 0 {
+            out_put += &format!("\n      Unexpected: {}", basic_info.unexpected_total);
+        }
+        if basic_info.timeout_total > 0 {
+            out_put += &format!("\n         Timeout: {}", basic_info.timeout_total);
+        }
+        if let Some(reason) = basic_info.aborted.as_ref() {
+            out_put += &format!("\n         Aborted: {}", reason);
+        }
+        if !extension_info.cookie.is_empty() {
+            let cookie: Vec<_> = extension_info
+                .cookie
+                .iter()
+                .map(|a| format!("{}={}", a.key, a.value))
+                .collect();
+            out_put += &format!("\n   Cookie Status: {}", cookie.join(","));
+        }
+        if !extension_info.tsig.is_empty() {
+            let tsig: Vec<_> = extension_info
+                .tsig
+                .iter()
+                .map(|a| format!("{}={}", a.key, a.value))
+                .collect();
+            out_put += &format!("\n     TSIG Status: {}", tsig.join(","));
+        }
+        if !extension_info.case.is_empty() {
+            let case: Vec<_> = extension_info
+                .case
+                .iter()
+                .map(|a| format!("{}={}", a.key, a.value))
+                .collect();
+            out_put += &format!("\n     0x20 Status: {}", case.join(","));
+        }
+        if !extension_info.dnssec.is_empty() {
+            let dnssec: Vec<_> = extension_info
+                .dnssec
+                .iter()
+                .map(|a| format!("{}={}", a.key, a.value))
+                .collect();
+            out_put += &format!("\n   DNSSEC Status: {}", dnssec.join(","));
+        }
+        if !extension_info.edns_option.is_empty() {
+            let edns_option: Vec<_> = extension_info
+                .edns_option
+                .iter()
+                .map(|a| format!("{}={}", a.key, a.value))
+                .collect();
+            out_put += &format!("\n     EDNS Option: {}", edns_option.join(","));
+        }
+        for mutation in extension_info.mutation.iter() {
+            let result: Vec<_> = mutation
+                .result
+                .iter()
+                .map(|a| format!("{}={}", a.key, a.value))
+                .collect();
+            out_put += &format!(
+                "\n        Mutation: {} {}",
+                mutation.mutation,
+                result.join(",")
+            );
+        }
+        let transfer = &extension_info.transfer;
+        if transfer.transfer_total > 0 {
+            let average = |total: u64| total as f64 / transfer.transfer_total as f64;
+            out_put += &format!(
+                "
+       Transfers: {}
+   Transfer Time: min={:?},max={:?},mean={:?}
+Transfer Records: {} ({:.1}/transfer)
+  Transfer Bytes: {} ({:.1}/transfer)
+Transfer Message: {} ({:.1}/transfer)",
+                transfer.transfer_total,
+                std::time::Duration::from_secs_f64(transfer.min_duration),
+                std::time::Duration::from_secs_f64(transfer.max_duration),
+                std::time::Duration::from_secs_f64(transfer.mean_duration),
+                transfer.record_total,
+                average(transfer.record_total),
+                transfer.byte_total,
+                average(transfer.byte_total),
+                transfer.message_total,
+                average(transfer.message_total),
+            );
+        }
+        if !transfer.failed.is_empty() {
+            let failed: Vec<_> = transfer
+                .failed
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            out_put += &format!("\n Transfer Failed: {}", failed.join(","));
+        }
+        let expectation = &extension_info.expectation;
+        if expectation.checked_total > 0 {
+            out_put += &format!(
+                "\n  Answer Checked: {}\n Answer Mismatch: {}",
+                expectation.checked_total, expectation.mismatch_total
+            );
+            for sample in expectation.samples.iter() {
+                out_put += &format!("\n                  {}", sample);
+            }
+        }
+        let compare = &extension_info.compare;
+        if compare.compared_total > 0 {
+            let category: Vec<_> = compare
+                .category
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            let latency = |status: &LatencyStatus| {
+                format!(
+                    "responses={},min={:?},max={:?},mean={:?}",
+                    status.response_total,
+                    std::time::Duration::from_secs_f64(status.min),
+                    std::time::Duration::from_secs_f64(status.max),
+                    std::time::Duration::from_secs_f64(status.mean),
+                )
+            };
+            out_put += &format!(
+                "
+        Compared: {}
+        Mismatch: {} {}
+ Primary Latency: {}
+  Second Latency: {}",
+                compare.compared_total,
+                compare.mismatch_total,
+                category.join(","),
+                latency(&compare.primary),
+                latency(&compare.secondary),
+            );
+            for sample in compare.samples.iter() {
+                out_put += &format!("\n                  {}", sample);
+            }
+        }
         println!("{}", out_put);
     }
     fn yaml(report: &RunnerReport, output: String) {
@@ -556,26 +1449,90 @@ impl ReportOutput for ReportType {
     }
 }
 
+// trust-dns doesn't decode the IXFR type code
+fn record_type(code: u16) -> RecordType {
+    match code {
+        251 => RecordType::IXFR,
+        _ => RecordType::from(code),
+    }
+}
+
 fn format_result(result_map: &HashMap<u16, u64>) -> Vec<(RecordType, u64)> {
     let mut to_tuple: Vec<_> = result_map.iter().collect();
     to_tuple.sort_by_key(|a| a.0);
     to_tuple
         .iter()
         .map(|a| {
-            let query_type = RecordType::from(*a.0);
+            let query_type = record_type(*a.0);
             (query_type, *a.1)
         })
         .collect::<Vec<(RecordType, u64)>>()
 }
 
-fn format_code_result(result_map: &HashMap<u8, u64>) -> Vec<(ResponseCode, u64)> {
+fn format_code_result(result_map: &HashMap<u16, u64>) -> Vec<(ResponseCode, u64)> {
     let mut to_tuple: Vec<_> = result_map.iter().collect();
     to_tuple.sort_by_key(|a| a.0);
     to_tuple
         .iter()
         .map(|a| {
-            let query_type = trust_dns_client::op::ResponseCode::from(0, *a.0);
+            let query_type: ResponseCode = (*a.0).into();
             (query_type, *a.1)
         })
         .collect::<Vec<(ResponseCode, u64)>>()
 }
+
+#[cfg(test)]
+mod test {
+    use crate::runner::report::{DistributionStatus, RateStatus, ResponseStatus, TTL_BUCKETS};
+    use trust_dns_client::op::Message;
+    use trust_dns_client::rr::{Name, RData, Record};
+
+    #[test]
+    fn test_distribution() {
+        let mut first = DistributionStatus::default();
+        first.update(30, &TTL_BUCKETS);
+        first.update(300, &TTL_BUCKETS);
+        let mut second = DistributionStatus::default();
+        second.update(59, &TTL_BUCKETS);
+        let merged = first + second + DistributionStatus::default();
+        assert_eq!(merged.total, 3);
+        assert_eq!((merged.min, merged.max), (30, 300));
+        assert!((merged.mean - 389.0 / 3.0).abs() < 1e-9);
+        assert_eq!(merged.buckets.get(&10), Some(&2));
+        assert_eq!(merged.buckets.get(&300), Some(&1));
+
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        let mut message = Message::new();
+        message.set_authoritative(true);
+        for (ip, ttl) in [("192.0.2.1", 300), ("192.0.2.2", 60)].iter() {
+            message.add_answer(Record::from_rdata(
+                name.clone(),
+                *ttl,
+                RData::A(ip.parse().unwrap()),
+            ));
+        }
+        let mut status = ResponseStatus::default();
+        status.update(&message);
+        status.update_size(1300);
+        assert_eq!(status.answer_count.buckets.get(&2), Some(&1));
+        assert_eq!(status.min_ttl.min, 60);
+        assert_eq!(status.size.buckets.get(&1232), Some(&1));
+        assert_eq!(status.flag.get("AA"), Some(&1));
+        assert_eq!(status.flag.get("TC"), None);
+    }
+
+    #[test]
+    fn test_rate_deviation() {
+        let first = RateStatus {
+            target: 0.5,
+            achieved: 0.5,
+        };
+        let second = RateStatus {
+            target: 0.5,
+            achieved: 0.4,
+        };
+        let total = first + second;
+        assert!((total.deviation() + 10.0).abs() < 1e-9);
+        assert_eq!(RateStatus::default().deviation(), 0.0);
+    }
+}
@@ -1,7 +1,13 @@
+use crate::runner::capacity::{CapacityReport, CapacitySearch, StepResult, Threshold};
+use crate::runner::control::{Control, ControlServer};
+use crate::runner::rate::RateProfile;
 use crate::runner::report::{RunnerReport, StatusStore};
 use crate::utils::utils::cpu_mode_to_cpu_cores;
-use crate::utils::{Argument, Protocol};
+use crate::utils::{Argument, Mode, Protocol};
+use crate::workers::compare::CompareWorker;
+use crate::workers::fuzz::FuzzWorker;
 use crate::workers::tcp::TCPWorker;
+use crate::workers::transfer::TransferWorker;
 use crate::workers::{
     // doh::DOHWorker, tcp::TCPWorker,  udp_async::UDPAsyncWorker,dot::DoTWorker,
     udp::UDPWorker,
@@ -11,17 +17,27 @@ use core_affinity;
 use core_affinity::CoreId;
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::sync::Arc;
 
 pub struct Runner {
     arguments: Argument,
     workers: Vec<(Box<dyn Worker>, CoreId)>,
     report: RunnerReport,
+    // the commands from control socket and signals
+    control: Arc<Control>,
+    // removes the control socket when the runner is dropped
+    server: Option<ControlServer>,
 }
 
 impl Runner {
     pub fn new(arguments: Argument) -> Result<Runner, String> {
         let protocol = arguments.protocol.clone();
         let worker_factory: fn(Argument) -> Box<dyn Worker> = match protocol {
+            _ if arguments.mode == Mode::Axfr || arguments.mode == Mode::Ixfr => {
+                TransferWorker::new
+            }
+            _ if arguments.mode == Mode::Fuzz => FuzzWorker::new,
+            _ if arguments.mode == Mode::Compare => CompareWorker::new,
             Protocol::TCP => TCPWorker::new,
             // Protocol::DOH => DOHWorker::new,
             Protocol::UDP => UDPWorker::new,
@@ -33,6 +49,11 @@ impl Runner {
             std::boxed::Box<(dyn Worker + 'static)>,
             core_affinity::CoreId,
         )> = vec![];
+        // the steps of capacity search share the control of search
+        let control = arguments
+            .control
+            .clone()
+            .unwrap_or_else(|| Arc::new(Control::new()));
         let bind_cpu = arguments.bind_cpu.clone();
         match cpu_mode_to_cpu_cores(bind_cpu) {
             Err(e) => {
@@ -43,15 +64,18 @@ impl Runner {
                 let core_number = v.len();
                 for (index, core_id) in v.iter().enumerate() {
                     let mut args = arguments.clone();
-                    if args.qps != 0 {
-                        args.qps = args.qps / core_number + {
-                            if index == 0 {
-                                args.qps % core_number
-                            } else {
-                                0
-                            }
-                        };
+                    args.arrival = args.arrival.split(index, core_number);
+                    // each worker paces the fractional share of the rate, the
+                    // integer share would be 0 (unlimited) for the low rate
+                    if args.rate_profile.is_none() && args.qps != 0 && !args.closed_loop {
+                        args.rate_profile = Some(RateProfile::Constant(args.qps as f64));
                     }
+                    args.rate_profile = args
+                        .rate_profile
+                        .map(|profile| profile.scale(1.0 / core_number as f64));
+                    args.rate_phase = index as f64 / core_number as f64;
+                    args.rate_share = 1.0 / core_number as f64;
+                    args.control = Some(control.clone());
                     if args.max != 0 {
                         args.max = args.max / core_number + {
                             if index == 0 {
@@ -73,6 +97,15 @@ impl Runner {
                     if args.client == 0 {
                         args.client = 1;
                     }
+                    if args.max_inflight != 0 {
+                        args.max_inflight = args.max_inflight / core_number + {
+                            if index == 0 {
+                                args.max_inflight % core_number
+                            } else {
+                                0
+                            }
+                        }
+                    }
                     debug!(
                         "worker_{}: client={} max={} qps={}",
                         index, args.client, args.max, args.qps
@@ -81,13 +114,95 @@ impl Runner {
                 }
             }
         }
+        let server = if arguments.control_socket.is_empty() {
+            None
+        } else {
+            Some(ControlServer::start(&arguments, control.clone())?)
+        };
         Ok(Runner {
             arguments: arguments.clone(),
             report: RunnerReport::new(),
             workers,
+            control,
+            server,
         })
     }
     pub fn run(&mut self) {
+        if self.arguments.capacity_search.is_some() {
+            return self.search();
+        }
+        let (query_store_total, response_store_total) = self.collect();
+        self.report.set_producer_report(query_store_total);
+        self.report
+            .set_consumer_report(response_store_total.clone());
+        self.report.set_histogram_report(response_store_total);
+        if let Some(reason) = self.control.abort_reason() {
+            self.report.set_abort(reason);
+        }
+        self.report.report(self.arguments.output.clone());
+        // the control socket is closed when the run ends
+        drop(self.server.take());
+    }
+
+    /// the control shared by the workers, the run stops gracefully when the
+    /// stop command is pushed.
+    pub fn control(&self) -> Arc<Control> {
+        self.control.clone()
+    }
+
+    /// run the steps of fixed qps and find the max qps which meets the
+    /// thresholds, the workers are created again for each step.
+    fn search(&mut self) {
+        let method = self.arguments.capacity_search.unwrap();
+        let threshold = Threshold {
+            max_loss: self.arguments.max_loss,
+            max_timeout: self.arguments.max_timeout,
+            max_p99: self.arguments.max_p99,
+        };
+        let mut search = CapacitySearch::new(
+            method,
+            self.arguments.search_min,
+            self.arguments.search_max,
+            self.arguments.search_step,
+        );
+        let mut curve = vec![];
+        println!("{}", CapacityReport::header_line());
+        while let Some(rate) = search.next_rate() {
+            // the curve until the interrupted step is reported
+            if self.control.stopped() {
+                break;
+            }
+            let mut args = self.arguments.clone();
+            args.capacity_search = None;
+            args.control = Some(self.control.clone());
+            args.qps = rate;
+            args.max = 0;
+            args.until_stop = self.arguments.step_time;
+            args.output_interval = 0;
+            let (query, response) = match Runner::new(args) {
+                Ok(mut runner) => runner.collect(),
+                Err(e) => {
+                    error!("start capacity search step fail: {}", e);
+                    return;
+                }
+            };
+            let step = StepResult::new(rate, &query, &response, &threshold);
+            println!("{}", CapacityReport::step_line(&step));
+            search.record(rate, step.passed);
+            curve.push(step);
+        }
+        CapacityReport {
+            method: method.to_string(),
+            threshold,
+            max_qps: search.result(),
+            curve,
+        }
+        .report(self.arguments.output.clone());
+    }
+
+    /// run the workers until all of them stop, returns the total status of
+    /// producers and consumers.
+    fn collect(&mut self) -> (StatusStore, StatusStore) {
         debug!("start runner and generate workers");
         let worker_number = self.workers.len();
         let mut query_store_total = StatusStore::new();
@@ -144,11 +259,7 @@ impl Runner {
             query_store_total = query_store_total + status.0;
             response_store_total = response_store_total + status.1;
         }
-        self.report.set_producer_report(query_store_total);
-        self.report
-            .set_consumer_report(response_store_total.clone());
-        self.report.set_histogram_report(response_store_total);
-        self.report.report(self.arguments.output.clone());
+        (query_store_total, response_store_total)
     }
 }
 
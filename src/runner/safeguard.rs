@@ -0,0 +1,180 @@
+use crate::utils::Argument;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// the totals are sampled at most once in the interval
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sample is the totals of queries, responses and SERVFAIL responses
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    time: Instant,
+    query: u64,
+    response: u64,
+    servfail: u64,
+}
+
+/// Safeguard ends the run when the target looks overwhelmed, the loss and
+/// SERVFAIL percents are checked over the last window and the silence is
+/// the seconds since the first query not followed by any response. The
+/// threshold of 0 is not checked.
+pub struct Safeguard {
+    max_loss: f64,
+    max_servfail: f64,
+    max_silence: Duration,
+    window: Duration,
+    samples: VecDeque<Sample>,
+    // the totals when the last response arrives
+    last_response: (u64, u64),
+    // the time of the first query sent after the last response
+    waiting_since: Option<Instant>,
+}
+
+impl Safeguard {
+    pub fn new(argument: &Argument) -> Safeguard {
+        Safeguard {
+            max_loss: argument.abort_on_loss,
+            max_servfail: argument.abort_on_servfail,
+            max_silence: Duration::from_secs(argument.abort_no_response as u64),
+            window: Duration::from_secs(argument.abort_window as u64),
+            samples: VecDeque::new(),
+            last_response: (0, 0),
+            waiting_since: None,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.max_loss > 0.0 || self.max_servfail > 0.0 || self.max_silence > Duration::from_secs(0)
+    }
+
+    /// forget the totals, for example the counters restart after warmup
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.last_response = (0, 0);
+        self.waiting_since = None;
+    }
+
+    /// check the totals at the time, returns the reason if the run should
+    /// abort.
+    pub fn check(
+        &mut self,
+        now: Instant,
+        query: u64,
+        response: u64,
+        servfail: u64,
+    ) -> Option<String> {
+        if !self.enabled() {
+            return None;
+        }
+        if response != self.last_response.1 {
+            self.last_response = (query, response);
+            self.waiting_since = None;
+        } else if query > self.last_response.0 && self.waiting_since.is_none() {
+            self.waiting_since = Some(now);
+        }
+        if let Some(since) = self.waiting_since {
+            if self.max_silence > Duration::from_secs(0)
+                && now.duration_since(since) >= self.max_silence
+            {
+                return Some(format!("no response in {}s", self.max_silence.as_secs()));
+            }
+        }
+
+        let due = match self.samples.back() {
+            Some(last) => now.duration_since(last.time) >= SAMPLE_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.samples.push_back(Sample {
+                time: now,
+                query,
+                response,
+                servfail,
+            });
+        }
+        // keep one sample at the beginning of window as the base
+        while self.samples.len() > 1 && now.duration_since(self.samples[1].time) >= self.window {
+            self.samples.pop_front();
+        }
+        let base = self.samples.front()?;
+        if now.duration_since(base.time) < self.window {
+            return None;
+        }
+        let queries = query - base.query;
+        let responses = (response - base.response).min(queries);
+        if self.max_loss > 0.0 && queries > 0 {
+            let loss = (queries - responses) as f64 * 100.0 / queries as f64;
+            if loss >= self.max_loss {
+                return Some(format!(
+                    "{:.2}% queries without response in the last {}s",
+                    loss,
+                    self.window.as_secs()
+                ));
+            }
+        }
+        let responses = response - base.response;
+        if self.max_servfail > 0.0 && responses > 0 {
+            let servfail = (servfail - base.servfail) as f64 * 100.0 / responses as f64;
+            if servfail >= self.max_servfail {
+                return Some(format!(
+                    "{:.2}% SERVFAIL responses in the last {}s",
+                    servfail,
+                    self.window.as_secs()
+                ));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::safeguard::Safeguard;
+    use crate::utils::Argument;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_safeguard() {
+        let mut argument = Argument::default();
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        assert!(Safeguard::new(&argument)
+            .check(at(100_000), 100, 0, 0)
+            .is_none());
+
+        argument.abort_on_loss = 20.0;
+        argument.abort_window = 10;
+        let mut safeguard = Safeguard::new(&argument);
+        assert!(safeguard.check(at(0), 0, 0, 0).is_none());
+        // not checked until the window is full
+        assert!(safeguard.check(at(5_000), 500, 0, 0).is_none());
+        assert!(safeguard.check(at(10_000), 1000, 900, 0).is_none());
+        // the loss of the last 10 seconds is 1000 of 1000
+        assert!(safeguard.check(at(15_000), 1500, 900, 0).is_none());
+        assert!(safeguard.check(at(20_000), 2000, 900, 0).is_some());
+        safeguard.reset();
+        assert!(safeguard.check(at(21_000), 0, 0, 0).is_none());
+
+        argument.abort_on_loss = 0.0;
+        argument.abort_on_servfail = 50.0;
+        let mut safeguard = Safeguard::new(&argument);
+        assert!(safeguard.check(at(0), 0, 0, 0).is_none());
+        assert!(safeguard.check(at(10_000), 100, 100, 49).is_none());
+        assert!(safeguard.check(at(20_000), 200, 200, 100).is_some());
+
+        argument.abort_on_servfail = 0.0;
+        argument.abort_no_response = 3;
+        let mut safeguard = Safeguard::new(&argument);
+        assert!(safeguard.check(at(0), 0, 0, 0).is_none());
+        // nothing is sent while paused
+        assert!(safeguard.check(at(60_000), 0, 0, 0).is_none());
+        assert!(safeguard.check(at(61_000), 1, 0, 0).is_none());
+        assert!(safeguard.check(at(63_000), 10, 1, 0).is_none());
+        assert!(safeguard.check(at(65_000), 20, 1, 0).is_none());
+        assert!(safeguard.check(at(67_000), 30, 1, 0).is_none());
+        assert_eq!(
+            safeguard.check(at(68_000), 40, 1, 0),
+            Some("no response in 3s".to_string())
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::str::FromStr;
+use trust_dns_client::op::{Message, MessageType, OpCode, Query, UpdateMessage};
+use trust_dns_client::rr::{DNSClass, Name, Record, RecordType};
+use trust_dns_client::serialize::txt::{Lexer, Parser};
+
+// the placeholder in template name replaced by the record index
+const INDEX_PLACEHOLDER: &str = "{n}";
+
+/// UpdateTemplate is the record template for dynamic update (RFC 2136) in
+/// zone file format, for example "host-{n} 300 A 192.0.2.1", the name is
+/// relative to the zone and "{n}" is replaced by the record index.
+#[derive(Debug, Clone)]
+pub struct UpdateTemplate {
+    zone: Name,
+    template: String,
+}
+
+impl UpdateTemplate {
+    pub fn new(zone: &str, template: &str) -> Result<UpdateTemplate, String> {
+        let zone = Name::from_str(zone)
+            .map_err(|e| format!("zone {} parse fail: {}", zone, e))?
+            .append_domain(&Name::root());
+        let update = UpdateTemplate {
+            zone,
+            template: template.to_string(),
+        };
+        update.record(0)?;
+        Ok(update)
+    }
+
+    /// the record of template with index
+    pub fn record(&self, index: usize) -> Result<Record, String> {
+        let line = self
+            .template
+            .replace(INDEX_PLACEHOLDER, index.to_string().as_str());
+        let (_, records) = Parser::new()
+            .parse(
+                Lexer::new(&line),
+                Some(self.zone.clone()),
+                Some(DNSClass::IN),
+            )
+            .map_err(|e| format!("update template {} parse fail: {}", self.template, e))?;
+        let record = records
+            .values()
+            .flat_map(|v| v.records_without_rrsigs())
+            .next()
+            .cloned()
+            .ok_or(format!("update template {} has no record", self.template))?;
+        if !self.zone.zone_of(record.name()) {
+            return Err(format!(
+                "update record {} is not in zone {}",
+                record.name(),
+                self.zone
+            ));
+        }
+        Ok(record)
+    }
+
+    /// build the update message which adds the record to the zone
+    pub fn add(&self, index: usize) -> Result<Message, String> {
+        let mut message = self.message();
+        message.add_update(self.record(index)?);
+        Ok(message)
+    }
+
+    /// build the update message which deletes the record from the zone
+    pub fn delete(&self, index: usize) -> Result<Message, String> {
+        let mut record = self.record(index)?;
+        // the class must be NONE and ttl must be zero (RFC 2136 section 2.5.4)
+        record.set_dns_class(DNSClass::NONE);
+        record.set_ttl(0);
+        let mut message = self.message();
+        message.add_update(record);
+        Ok(message)
+    }
+
+    fn message(&self) -> Message {
+        let mut zone = Query::new();
+        zone.set_name(self.zone.clone())
+            .set_query_class(DNSClass::IN)
+            .set_query_type(RecordType::SOA);
+        let mut message = Message::new();
+        message
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Update)
+            .set_recursion_desired(false);
+        message.add_zone(zone);
+        message
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::update::UpdateTemplate;
+    use trust_dns_client::op::{Message, OpCode, UpdateMessage};
+    use trust_dns_client::rr::{DNSClass, Name, RecordType};
+
+    #[test]
+    fn test_update_template() {
+        let template = UpdateTemplate::new("example.com", "host-{n} 300 A 192.0.2.1").unwrap();
+        let record = template.record(7).unwrap();
+        assert_eq!(
+            record.name(),
+            &Name::from_ascii("host-7.example.com.").unwrap()
+        );
+        assert_eq!(record.ttl(), 300);
+        assert_eq!(record.record_type(), RecordType::A);
+
+        let add = Message::from_vec(&template.add(1).unwrap().to_vec().unwrap()).unwrap();
+        assert_eq!(add.op_code(), OpCode::Update);
+        assert_eq!(
+            add.zones()[0].name(),
+            &Name::from_ascii("example.com.").unwrap()
+        );
+        assert_eq!(add.updates()[0].dns_class(), DNSClass::IN);
+        let delete = Message::from_vec(&template.delete(1).unwrap().to_vec().unwrap()).unwrap();
+        assert_eq!(delete.updates()[0].dns_class(), DNSClass::NONE);
+        assert_eq!(delete.updates()[0].ttl(), 0);
+
+        assert!(UpdateTemplate::new("example.com", "host 300 A not-an-ip").is_err());
+        assert!(UpdateTemplate::new("example.com", "host.example.org. 300 A 192.0.2.1").is_err());
+    }
+}
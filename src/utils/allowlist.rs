@@ -0,0 +1,177 @@
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+// the networks always allowed, loopback and private networks
+const DEFAULT_NETWORKS: [&str; 6] = [
+    "127.0.0.0/8",
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "::1/128",
+    "fc00::/7",
+];
+
+// the public resolvers named in the error for the common mistakes
+const PUBLIC_RESOLVERS: [&str; 10] = [
+    "8.8.8.8",
+    "8.8.4.4",
+    "1.1.1.1",
+    "1.0.0.1",
+    "9.9.9.9",
+    "208.67.222.222",
+    "114.114.114.114",
+    "223.5.5.5",
+    "223.6.6.6",
+    "119.29.29.29",
+];
+
+/// Network is the address with prefix length, example: 10.0.0.0/8
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Network {
+    type Err = String;
+    /// parse the network "<address>/<prefix>", the single address is the
+    /// network of itself
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let mut items = network.trim().splitn(2, '/');
+        let address = IpAddr::from_str(items.next().unwrap_or(""))
+            .map_err(|_| format!("network {} address not valid", network))?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match items.next() {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(v) if v <= max => v,
+                _ => return Err(format!("network {} prefix not valid", network)),
+            },
+            None => max,
+        };
+        Ok(Network { address, prefix })
+    }
+}
+
+impl Network {
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(*address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(*address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// AllowList is the target networks which can be load tested at any rate,
+/// the loopback and private networks are always in the list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllowList {
+    networks: Vec<Network>,
+}
+
+impl Default for AllowList {
+    fn default() -> Self {
+        AllowList {
+            networks: DEFAULT_NETWORKS
+                .iter()
+                .map(|network| network.parse().unwrap())
+                .collect(),
+        }
+    }
+}
+
+impl AllowList {
+    /// load the networks from file, each line is a network or address and
+    /// the text after "#" is comment.
+    pub fn from_file(file: &str) -> Result<AllowList, String> {
+        let content = fs::read_to_string(file)
+            .map_err(|e| format!("read allow-list {} fail: {}", file, e))?;
+        AllowList::from_lines(&content)
+    }
+
+    pub fn from_lines(content: &str) -> Result<AllowList, String> {
+        let mut allow_list = AllowList::default();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if !line.is_empty() {
+                allow_list.networks.push(line.parse()?);
+            }
+        }
+        Ok(allow_list)
+    }
+
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        self.networks
+            .iter()
+            .any(|network| network.contains(address))
+    }
+
+    /// check the server resolved to the addresses all in the list, returns
+    /// the reason if not.
+    pub fn check(&self, server: &str, port: u16) -> Result<(), String> {
+        let addresses: Vec<IpAddr> = match (server, port).to_socket_addrs() {
+            Ok(addresses) => addresses.map(|address| address.ip()).collect(),
+            Err(e) => return Err(format!("{} can not be resolved: {}", server, e)),
+        };
+        match addresses.iter().find(|address| !self.contains(address)) {
+            None if !addresses.is_empty() => Ok(()),
+            None => Err(format!("{} is resolved to no address", server)),
+            Some(address) if PUBLIC_RESOLVERS.contains(&address.to_string().as_str()) => {
+                Err(format!("{} is a public resolver", address))
+            }
+            Some(address) => Err(format!("{} is not in the allow-list", address)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::allowlist::{AllowList, Network};
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_allow_list() {
+        let ip = |v: &str| IpAddr::from_str(v).unwrap();
+        let network: Network = "172.16.0.0/12".parse().unwrap();
+        assert!(network.contains(&ip("172.31.255.1")));
+        assert!(!network.contains(&ip("172.32.0.1")));
+        assert!(!network.contains(&ip("::1")));
+        assert!("0.0.0.0/0"
+            .parse::<Network>()
+            .unwrap()
+            .contains(&ip("8.8.8.8")));
+        assert!("203.0.113.7"
+            .parse::<Network>()
+            .unwrap()
+            .contains(&ip("203.0.113.7")));
+        assert!("10.0.0.0/33".parse::<Network>().is_err());
+        assert!("example.com/8".parse::<Network>().is_err());
+
+        let allow_list =
+            AllowList::from_lines("# staging\n198.51.100.0/24 # resolvers\n\n2001:db8::/32\n")
+                .unwrap();
+        assert!(allow_list.contains(&ip("198.51.100.53")));
+        assert!(allow_list.contains(&ip("2001:db8::53")));
+        assert!(allow_list.contains(&ip("192.168.1.1")));
+        assert!(!allow_list.contains(&ip("198.51.101.53")));
+        assert!(AllowList::from_lines("300.0.0.1").is_err());
+
+        assert!(allow_list.check("127.0.0.1", 53).is_ok());
+        assert_eq!(
+            allow_list.check("8.8.8.8", 53),
+            Err("8.8.8.8 is a public resolver".to_string())
+        );
+        assert_eq!(
+            allow_list.check("203.0.113.1", 53),
+            Err("203.0.113.1 is not in the allow-list".to_string())
+        );
+    }
+}
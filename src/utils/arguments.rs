@@ -1,10 +1,20 @@
-use crate::utils::utils::cpu_mode_to_cpu_cores;
+use crate::runner::cache::check_padding;
+use crate::runner::capacity::SearchMethod;
+use crate::runner::control::Control;
+use crate::runner::rate::{Arrival, RateProfile};
+use crate::runner::update::UpdateTemplate;
+use crate::utils::allowlist::AllowList;
+use crate::utils::dnssec::TrustAnchor;
+use crate::utils::tsig::TsigKey;
+use crate::utils::utils::{cpu_mode_to_cpu_cores, parse_hex};
+use crate::utils::wire::parse_raw_message;
 use num_cpus;
 use std::fmt;
 use std::fmt::Formatter;
 use std::fs;
 use std::net::IpAddr;
 use std::str::FromStr;
+use std::sync::Arc;
 use structopt::StructOpt;
 use trust_dns_client::proto::rr::RecordType;
 use trust_dns_client::rr::Name;
@@ -36,6 +46,34 @@ impl FromStr for Protocol {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Mode {
+    #[default]
+    Query,
+    Update,
+    Axfr,
+    Ixfr,
+    Notify,
+    Fuzz,
+    Compare,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.to_uppercase().as_str() {
+            "QUERY" => Ok(Mode::Query),
+            "UPDATE" => Ok(Mode::Update),
+            "AXFR" => Ok(Mode::Axfr),
+            "IXFR" => Ok(Mode::Ixfr),
+            "NOTIFY" => Ok(Mode::Notify),
+            "FUZZ" => Ok(Mode::Fuzz),
+            "COMPARE" => Ok(Mode::Compare),
+            _ => Err(format!("mode {} not valid", mode)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DoHMethod {
     Get,
@@ -58,12 +96,49 @@ impl FromStr for DoHMethod {
         }
     }
 }
+/// edns option set by code and hex data, for example 65001:abcd
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdnsOpt {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl FromStr for EdnsOpt {
+    type Err = String;
+    fn from_str(option: &str) -> Result<Self, Self::Err> {
+        let mut splitter = option.splitn(2, ':');
+        let code = match splitter.next().unwrap_or("").parse::<u16>() {
+            Ok(v) => v,
+            _ => return Err(format!("edns option {} code not valid", option)),
+        };
+        let data = parse_hex(splitter.next().unwrap_or(""))
+            .map_err(|e| format!("edns option {} data not valid: {}", option, e))?;
+        Ok(EdnsOpt { code, data })
+    }
+}
+
+impl fmt::Display for EdnsOpt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.code)?;
+        for byte in self.data.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
 fn parse_ip(value: &str) -> Result<IpAddr, String> {
     match IpAddr::from_str(value) {
         Ok(v) => Ok(v),
         _ => Err(format!("source ip address {} not correct!", value)),
     }
 }
+fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.trim_end_matches('%').parse::<f64>() {
+        Ok(v) if (0.0..=100.0).contains(&v) => Ok(v),
+        _ => Err(format!("percent {} should be between 0 and 100", value)),
+    }
+}
 fn parse_server(value: &str) -> Result<String, String> {
     let mut is_ip = false;
     let mut is_domain = false;
@@ -95,13 +170,69 @@ OPTIONS:
     -d, --domain <domain>                          domain name for dns query [default: example.com]
     -t, --type <qty>                               dns query type [default: A]
     -T, --time <time>                              how long it will send until stop [default: 0]
+        --warmup <second>                          send queries for seconds before -T and -m start, the queries and
+                                                   responses of warmup are not counted in report [default: 0]
     -q, --qps <qps>                                dns query per second [default: 10]
+        --rate-profile <profile>                   change the query rate over time instead of fixed qps, support
+                                                   linear:<from>:<to>:<seconds>, step:<qps>,<qps>,...:<seconds>
+                                                   and sine:<mean>:<amplitude>:<period>
+        --rate-schedule <file>                     load the query rate schedule from csv file, each line is <seconds>,<qps>
+        --arrival <process>                        how the queries are spaced at the query rate, support constant,
+                                                   poisson and burst:<queries>:<ms> which ignores qps [default: constant]
+        --capacity-search <method>                 search the max qps which meets the thresholds by running steps
+                                                   of fixed qps, support methods [ramp, binary]
+        --search-min <qps>                         the first qps of capacity search [default: 100]
+        --search-max <qps>                         the highest qps of capacity search [default: 10000]
+        --search-step <qps>                        the qps increase of ramp and the precision of binary search [default: 100]
+        --step-time <second>                       how long each step of capacity search sends [default: 10]
+        --max-loss <percent>                       the max percent of queries without response in a step [default: 1]
+        --max-timeout <percent>                    the max percent of queries not answered in timeout in a step [default: 1]
+        --max-p99 <ms>                             the max 99% latency in milliseconds of a step, 0 means not checked [default: 0]
+        --abort-on-loss <percent>                  stop with the report and exit code 3 if the percent of queries without
+                                                   response in --abort-window reaches it, 0 means not checked [default: 0]
+        --abort-on-servfail <percent>              stop like --abort-on-loss if the percent of SERVFAIL responses in
+                                                   --abort-window reaches it, 0 means not checked [default: 0]
+        --abort-no-response <second>               stop like --abort-on-loss if no response arrives in seconds after
+                                                   a query is sent, 0 means not checked [default: 0]
+        --abort-window <second>                    the sliding window of the abort thresholds [default: 10]
     -m, --max <max>                                max dns packets will be send [default: 100]
     -c, --client <client>                          concurrent clients numbers, set to 0 will replace with the number of cpu cores [default: 0]
+        --max-inflight <number>                    the max number of queries waiting for response across the clients,
+                                                   each client has one at most, 0 means no limit [default: 0]
     -f, --file <file>                              the dns query file, default using -d for single domain query [default: \"\"]
+        --raw <message>...                         send the raw wire message in hex or base64 with \"base64:\" prefix,
+                                                   example: base64:AAEBAAABAAAAAAAAB2V4YW1wbGUDY29tAAABAAE=
     -o, --output <file>                            format output report to stdout, .json or .yaml file [default: \"stdout\"]
     -I, --interval <second>                        output result interval for dns benchmark [default: 0]
+        --allow-list <file>                        the networks can be tested above --unlisted-qps besides the loopback
+                                                   and private networks, one network each line, example: 192.0.2.0/24,
+                                                   also read from env SND_ALLOW_LIST
+        --unlisted-qps <qps>                       the max qps to the servers off the allow-list without
+                                                   --i-own-this-target [default: 100]
+        --control <socket>                         accept the commands on unix socket to change the running benchmark,
+                                                   support qps <qps>, pause, resume, file <file>, domain <domain> [type]
+                                                   and stop
         --edns-size <edns-size>                    set opt max EDNS buffer size [default: 1232]
+        --edns-padding <block>                     pad query to a multiple of block size with EDNS padding option,
+                                                   needs dot or doh (not supported yet), 0 means no padding [default: 0]
+        --edns-option <code:hex>...                add EDNS option with code and hex data, example: 65001:abcd
+        --tsig <name:alg:secret>                   sign query with TSIG key, secret is base64 encoded,
+                                                   example: key1:hmac-sha256:c2VjcmV0
+        --tsig-file <file>                         load TSIG key from BIND style key file
+        --dnssec-anchor <file>                     validate the signed responses with the DNSKEY records in file
+                                                   as trust anchor, the DO bit is set in query
+        --mode <mode>                              the benchmark mode [default: QUERY]
+                                                   support modes [QUERY, UPDATE, AXFR, IXFR, NOTIFY, FUZZ, COMPARE]
+        --zone <zone>                              the zone for dynamic update or zone transfer, the zones
+                                                   for NOTIFY are separated by comma
+        --update-template <record>                 the record template added and deleted in UPDATE mode, {n} in name
+                                                   is replaced by record index [default: \"snd-{n} 300 A 192.0.2.1\"]
+        --update-records <number>                  the number of records generated from template [default: 100]
+        --ixfr-serial <serial>                     the SOA serial of the client in IXFR mode [default: 0]
+        --transfer-interval <second>               wait seconds before repeat the transfer on a connection [default: 0]
+        --notify-serial <serial>                   add the SOA record with serial to answer section in NOTIFY mode
+        --compare-server <server>                  the second dns server whose responses are compared in COMPARE mode
+        --compare-port <port>                      the second dns server port number [default: 53]
         --protocol <protocol>                      the packet protocol for send dns request [default: UDP]
                                                    support protocols [UDP, TCP]
         --source-ip <source>                       set the source ip address [default: 0.0.0.0]
@@ -111,10 +242,20 @@ OPTIONS:
 
 FLAGS:
         --debug                enable debug mode
+        --raw-file             each line of query file is a raw wire message in hex or base64
+        --keep-id              send the raw wire message without rewriting the message id
+        --random-id            allocate random message id instead of sequential id for each socket
         --disable-edns         disable EDNS
         --disable-rd           RD (recursion desired) bit in the query
         --enable-cd            CD (checking disabled) bit in the query
         --enable-dnssec        enable dnssec
+        --enable-cookie        send dns cookie(RFC7873) and echo the server cookie learned from response
+        --enable-0x20          randomize the case of query name (dns-0x20) and check the response echoes it
+        --nsid                 request name server identifier (NSID) in EDNS
+        --tcp-keepalive        send EDNS TCP keepalive option (RFC7828), only for TCP
+        --ede                  count extended dns errors (RFC8914) of responses by info code and extra text
+        --closed-loop          send the next query only when the response arrives or the timeout expires, qps is ignored
+        --i-own-this-target    confirm the high rate run against the server off the allow-list
 HELP:
     -h, --help                 Prints help information
 VERSION:
@@ -147,20 +288,122 @@ pub struct Argument {
     #[structopt(long = "file-loop")]
     pub fileloop: bool,
 
+    #[structopt(long = "raw", number_of_values = 1)]
+    pub raw: Vec<String>,
+
+    #[structopt(long = "raw-file")]
+    pub raw_file: bool,
+
+    #[structopt(long = "keep-id")]
+    pub keep_id: bool,
+
+    #[structopt(long = "random-id")]
+    pub random_id: bool,
+
     #[structopt(long = "protocol", default_value = "UDP")]
     pub protocol: Protocol,
 
+    #[structopt(long = "mode", default_value = "QUERY")]
+    pub mode: Mode,
+
+    #[structopt(long = "zone", default_value = "")]
+    pub zone: String,
+
+    #[structopt(long = "update-template", default_value = "snd-{n} 300 A 192.0.2.1")]
+    pub update_template: String,
+
+    #[structopt(long = "update-records", default_value = "100")]
+    pub update_records: usize,
+
+    #[structopt(long = "ixfr-serial", default_value = "0")]
+    pub ixfr_serial: u32,
+
+    #[structopt(long = "transfer-interval", default_value = "0")]
+    pub transfer_interval: usize,
+
+    #[structopt(long = "notify-serial")]
+    pub notify_serial: Option<u32>,
+
+    #[structopt(long = "compare-server", default_value = "")]
+    pub compare_server: String,
+
+    #[structopt(long = "compare-port", default_value = "53")]
+    pub compare_port: u16,
+
     #[structopt(short = "q", long = "qps", default_value = "10")]
     pub qps: usize,
+
+    #[structopt(long = "rate-profile")]
+    pub rate_profile: Option<RateProfile>,
+
+    #[structopt(long = "rate-schedule", default_value = "")]
+    pub rate_schedule: String,
+
+    #[structopt(long = "arrival", default_value = "constant")]
+    pub arrival: Arrival,
+
+    // the start phase of the worker in the interval between queries
+    #[structopt(skip)]
+    pub rate_phase: f64,
+
+    // the share of the total query rate sent by the worker
+    #[structopt(skip = 1.0)]
+    pub rate_share: f64,
+
+    #[structopt(long = "capacity-search")]
+    pub capacity_search: Option<SearchMethod>,
+
+    #[structopt(long = "search-min", default_value = "100")]
+    pub search_min: usize,
+
+    #[structopt(long = "search-max", default_value = "10000")]
+    pub search_max: usize,
+
+    #[structopt(long = "search-step", default_value = "100")]
+    pub search_step: usize,
+
+    #[structopt(long = "step-time", default_value = "10")]
+    pub step_time: usize,
+
+    #[structopt(long = "max-loss", default_value = "1")]
+    pub max_loss: f64,
+
+    #[structopt(long = "max-timeout", default_value = "1")]
+    pub max_timeout: f64,
+
+    #[structopt(long = "max-p99", default_value = "0")]
+    pub max_p99: f64,
+
+    #[structopt(long = "abort-on-loss", default_value = "0", parse(try_from_str = parse_percent))]
+    pub abort_on_loss: f64,
+
+    #[structopt(long = "abort-on-servfail", default_value = "0", parse(try_from_str = parse_percent))]
+    pub abort_on_servfail: f64,
+
+    #[structopt(long = "abort-no-response", default_value = "0")]
+    pub abort_no_response: usize,
+
+    #[structopt(long = "abort-window", default_value = "10")]
+    pub abort_window: usize,
+
     #[structopt(short = "m", long = "max", default_value = "0")]
     pub max: usize,
 
     #[structopt(short = "T", long = "time", default_value = "0")]
     pub until_stop: usize,
 
+    #[structopt(long = "warmup", default_value = "0")]
+    pub warmup: usize,
+
     #[structopt(short = "c", long = "client", default_value = "0")]
     pub client: usize,
 
+    #[structopt(long = "max-inflight", default_value = "0")]
+    pub max_inflight: usize,
+
+    #[structopt(long = "closed-loop")]
+    pub closed_loop: bool,
+
     #[structopt(short = "d", long = "domain", default_value = "example.com")]
     pub domain: String,
     #[structopt(short = "t", long = "type", default_value = "A")]
@@ -189,6 +432,12 @@ pub struct Argument {
     #[structopt(long = "enable-dnssec")]
     pub enable_dnssec: bool,
 
+    #[structopt(long = "enable-cookie")]
+    pub enable_cookie: bool,
+
+    #[structopt(long = "enable-0x20")]
+    pub enable_0x20: bool,
+
     #[structopt(long = "disable-edns")]
     // set the default max payload to 1232
     // https://dnsflagday.net/2020/
@@ -196,6 +445,34 @@ pub struct Argument {
     #[structopt(long = "edns-size", default_value = "1232")]
     pub edns_size: u16,
 
+    #[structopt(long = "edns-padding", default_value = "0")]
+    pub edns_padding: usize,
+
+    #[structopt(long = "edns-option", number_of_values = 1)]
+    pub edns_option: Vec<EdnsOpt>,
+
+    #[structopt(long = "nsid")]
+    pub nsid: bool,
+
+    #[structopt(long = "tcp-keepalive")]
+    pub tcp_keepalive: bool,
+
+    #[structopt(long = "ede")]
+    pub ede: bool,
+
+    #[structopt(long = "tsig")]
+    pub tsig: Option<TsigKey>,
+
+    #[structopt(long = "tsig-file", default_value = "")]
+    pub tsig_file: String,
+
+    #[structopt(long = "dnssec-anchor", default_value = "")]
+    pub dnssec_anchor: String,
+
+    // loaded from the dnssec anchor file when validating arguments
+    #[structopt(skip)]
+    pub trust_anchor: Option<TrustAnchor>,
+
     #[structopt(long = "debug")]
     pub debug: bool,
 
@@ -213,6 +490,22 @@ pub struct Argument {
 
     #[structopt(short = "I", long = "interval", default_value = "0")]
     pub output_interval: usize,
+
+    #[structopt(long = "allow-list", env = "SND_ALLOW_LIST", default_value = "")]
+    pub allow_list: String,
+
+    #[structopt(long = "unlisted-qps", default_value = "100")]
+    pub unlisted_qps: usize,
+
+    #[structopt(long = "i-own-this-target")]
+    pub i_own_this_target: bool,
+
+    #[structopt(long = "control", default_value = "")]
+    pub control_socket: String,
+
+    // the commands of control socket shared by the workers
+    #[structopt(skip)]
+    pub control: Option<Arc<Control>>,
 }
 
 impl Argument {
@@ -237,18 +530,303 @@ impl Argument {
                 return Err(format!("open file {} error", self.file));
             }
         }
+        if self.mode == Mode::Update {
+            if self.zone.is_empty() {
+                return Err("must set the zone [--zone] in update mode".to_string());
+            }
+            if !self.file.is_empty() {
+                return Err("query file is not supported in update mode".to_string());
+            }
+            if self.update_records == 0 {
+                return Err("update records must be greater than 0".to_string());
+            }
+            UpdateTemplate::new(self.zone.as_str(), self.update_template.as_str())?;
+        }
+        if self.mode == Mode::Axfr || self.mode == Mode::Ixfr {
+            if self.zone.is_empty() {
+                return Err("must set the zone [--zone] in transfer mode".to_string());
+            }
+            if let Err(e) = Name::from_str(self.zone.as_str()) {
+                return Err(format!("zone {} parse fail: {}", self.zone, e));
+            }
+            if !self.file.is_empty() {
+                return Err("query file is not supported in transfer mode".to_string());
+            }
+            match self.protocol {
+                Protocol::TCP => {}
+                Protocol::DOT => {
+                    return Err(
+                        "zone transfer over dot is not supported yet, use --protocol tcp"
+                            .to_string(),
+                    )
+                }
+                _ => return Err("zone transfer only support TCP protocol".to_string()),
+            }
+        }
+        if self.raw_input() {
+            if self.mode != Mode::Query && self.mode != Mode::Fuzz && self.mode != Mode::Compare {
+                return Err(
+                    "raw message is only supported in query, fuzz and compare mode".to_string(),
+                );
+            }
+            if self.raw_file && self.file.is_empty() {
+                return Err("must set the query file [-f] with --raw-file".to_string());
+            }
+            for raw in self.raw.iter() {
+                parse_raw_message(raw)?;
+            }
+            // the raw message is sent byte-for-byte, nothing can be appended
+            if self.enable_cookie
+                || self.enable_0x20
+                || self.tsig.is_some()
+                || !self.tsig_file.is_empty()
+            {
+                return Err(
+                    "dns cookie, 0x20 and tsig can not be used with raw message".to_string()
+                );
+            }
+        } else if self.keep_id {
+            return Err("--keep-id must be used with raw message".to_string());
+        }
+        if self.random_id && (self.packet_id != 0 || self.keep_id) {
+            return Err("--random-id can not be used with --packet-id or --keep-id".to_string());
+        }
+        if self.mode == Mode::Fuzz {
+            match self.protocol {
+                Protocol::UDP => {}
+                _ => return Err("fuzz mode only support UDP protocol".to_string()),
+            }
+            // the worker sends from one socket
+            match self.client {
+                0 => self.client = 1,
+                1 => {}
+                _ => return Err("fuzz mode only support one client [-c 1]".to_string()),
+            }
+        }
+        if self.mode == Mode::Compare {
+            if self.compare_server.is_empty() {
+                return Err(
+                    "must set the second server [--compare-server] in compare mode".to_string(),
+                );
+            }
+            parse_server(self.compare_server.as_str())?;
+            match self.protocol {
+                Protocol::UDP | Protocol::TCP => {}
+                _ => return Err("compare mode only support UDP and TCP protocol".to_string()),
+            }
+            // the worker waits for each query in turn
+            match self.client {
+                0 => self.client = 1,
+                1 => {}
+                _ => return Err("compare mode only support one client [-c 1]".to_string()),
+            }
+        }
+        if self.mode == Mode::Notify {
+            if self.zone.is_empty() && self.file.is_empty() {
+                return Err(
+                    "must set the zone [--zone] or zone file [-f] in notify mode".to_string(),
+                );
+            }
+            for zone in self.zone.split(',').filter(|z| !z.is_empty()) {
+                if let Err(e) = Name::from_str(zone) {
+                    return Err(format!("zone {} parse fail: {}", zone, e));
+                }
+            }
+        }
         if self.domain.is_empty() && self.file.is_empty() {
             return Err(format!("must set domain or query file"));
         }
-        if self.until_stop == 0 && self.max == 0 {
+        if self.capacity_search.is_some() {
+            if self.mode != Mode::Query {
+                return Err("capacity search is only supported in query mode".to_string());
+            }
+            match self.protocol {
+                Protocol::UDP | Protocol::TCP => {}
+                _ => return Err("capacity search only support UDP and TCP protocol".to_string()),
+            }
+            if self.rate_profile.is_some() || !self.rate_schedule.is_empty() {
+                return Err("capacity search can not be used with rate profile".to_string());
+            }
+            if self.search_min == 0 || self.search_min > self.search_max {
+                return Err(
+                    "capacity search range should be 0 < --search-min <= --search-max".to_string(),
+                );
+            }
+            if self.search_step == 0 || self.step_time == 0 {
+                return Err("--search-step and --step-time must be greater than 0".to_string());
+            }
+            if self.max_loss < 0.0 || self.max_timeout < 0.0 || self.max_p99 < 0.0 {
+                return Err("capacity search threshold must not be negative".to_string());
+            }
+        } else if self.until_stop == 0 && self.max == 0 {
             return Err(format!("must set max query [-m] or time limit [-T]"));
         }
         if self.client == 0 {
             self.client = num_cpus::get();
         }
+        if self.warmup > 0 {
+            if self.mode != Mode::Query {
+                return Err("warmup is only supported in query mode".to_string());
+            }
+            match self.protocol {
+                Protocol::UDP | Protocol::TCP => {}
+                _ => return Err("warmup only support UDP and TCP protocol".to_string()),
+            }
+        }
+        if self.max_inflight > 0 || self.closed_loop {
+            if self.mode != Mode::Query {
+                return Err(
+                    "--max-inflight and --closed-loop are only supported in query mode".to_string(),
+                );
+            }
+            match self.protocol {
+                Protocol::UDP | Protocol::TCP => {}
+                _ => {
+                    return Err(
+                        "--max-inflight and --closed-loop only support UDP and TCP protocol"
+                            .to_string(),
+                    )
+                }
+            }
+        }
+        if self.closed_loop
+            && (self.rate_profile.is_some()
+                || !self.rate_schedule.is_empty()
+                || self.capacity_search.is_some())
+        {
+            return Err(
+                "closed loop can not be used with rate profile or capacity search".to_string(),
+            );
+        }
+        match self.arrival {
+            Arrival::Constant => {}
+            _ if self.closed_loop => {
+                return Err("closed loop can not be used with --arrival".to_string());
+            }
+            Arrival::Poisson if self.qps == 0 && self.rate_profile.is_none() => {
+                return Err("poisson arrival need the query rate [-q]".to_string());
+            }
+            Arrival::Poisson => {}
+            Arrival::Burst(size, _) => {
+                if self.rate_profile.is_some() || self.capacity_search.is_some() {
+                    return Err(
+                        "burst arrival can not be used with rate profile or capacity search"
+                            .to_string(),
+                    );
+                }
+                let workers = cpu_mode_to_cpu_cores(self.bind_cpu.clone())
+                    .map_err(|e| e.to_string())?
+                    .len();
+                if (size as usize) < workers {
+                    return Err(format!(
+                        "burst size should not be less than the {} worker threads",
+                        workers
+                    ));
+                }
+            }
+        }
+        if self.max_inflight > 0 {
+            // each worker thread keeps its share of the in-flight queries
+            let workers = cpu_mode_to_cpu_cores(self.bind_cpu.clone())
+                .map_err(|e| e.to_string())?
+                .len();
+            if self.max_inflight < workers {
+                return Err(format!(
+                    "--max-inflight should not be less than the {} worker threads",
+                    workers
+                ));
+            }
+        }
+        if self.abort_on_loss > 0.0 || self.abort_on_servfail > 0.0 || self.abort_no_response > 0 {
+            if self.mode != Mode::Query {
+                return Err("abort thresholds are only supported in query mode".to_string());
+            }
+            match self.protocol {
+                Protocol::UDP | Protocol::TCP => {}
+                _ => return Err("abort thresholds only support UDP and TCP protocol".to_string()),
+            }
+            if self.capacity_search.is_some() {
+                return Err(
+                    "abort thresholds can not be used with capacity search, use --max-loss instead"
+                        .to_string(),
+                );
+            }
+            if self.abort_window == 0 {
+                return Err("--abort-window must be greater than 0".to_string());
+            }
+        }
+        if !self.control_socket.is_empty() {
+            if self.mode != Mode::Query {
+                return Err("control socket is only supported in query mode".to_string());
+            }
+            match self.protocol {
+                Protocol::UDP | Protocol::TCP => {}
+                _ => return Err("control socket only support UDP and TCP protocol".to_string()),
+            }
+            if self.capacity_search.is_some() {
+                return Err("control socket can not be used with capacity search".to_string());
+            }
+        }
+        if self.enable_cookie && self.disable_edns {
+            return Err("dns cookie must be sent with edns, remove --disable-edns".to_string());
+        }
+        if self.disable_edns
+            && (self.edns_padding > 0
+                || self.nsid
+                || self.tcp_keepalive
+                || !self.edns_option.is_empty())
+        {
+            return Err("edns options must be sent with edns, remove --disable-edns".to_string());
+        }
+        if self.edns_padding > 0 {
+            check_padding(&self.protocol)?;
+        }
+        if self.ede && self.disable_edns {
+            return Err(
+                "extended dns error is only sent with edns, remove --disable-edns".to_string(),
+            );
+        }
+        if self.enable_0x20 && self.mode != Mode::Query {
+            return Err("0x20 case randomization is only supported in query mode".to_string());
+        }
+        if self.tcp_keepalive {
+            if let Protocol::UDP = self.protocol {
+                return Err("edns tcp keepalive option must not be sent over udp".to_string());
+            }
+        }
+        if !self.tsig_file.is_empty() {
+            if self.tsig.is_some() {
+                return Err("only one of --tsig and --tsig-file can be set".to_string());
+            }
+            self.tsig = Some(TsigKey::from_file(self.tsig_file.as_str())?);
+        }
+        if !self.dnssec_anchor.is_empty() {
+            if self.mode != Mode::Query && self.mode != Mode::Compare {
+                return Err(
+                    "dnssec validation is only supported in query and compare mode".to_string(),
+                );
+            }
+            if self.disable_edns {
+                return Err(
+                    "dnssec validation need the DO bit of edns, remove --disable-edns".to_string(),
+                );
+            }
+            if self.raw_input() {
+                return Err("dnssec validation can not be used with raw message".to_string());
+            }
+            self.trust_anchor = Some(TrustAnchor::from_file(self.dnssec_anchor.as_str())?);
+            self.enable_dnssec = true;
+        }
+        if !self.rate_schedule.is_empty() {
+            if self.rate_profile.is_some() {
+                return Err("only one of --rate-profile and --rate-schedule can be set".to_string());
+            }
+            self.rate_profile = Some(RateProfile::from_file(self.rate_schedule.as_str())?);
+        }
         if let Err(e) = cpu_mode_to_cpu_cores(self.bind_cpu.clone()) {
             return Err(e.to_string());
         }
+        self.check_target(self.peak_qps())?;
 
         if !(self.output.to_lowercase().ends_with(".json")
             || self.output.to_lowercase().ends_with(".yaml")
@@ -259,6 +837,71 @@ impl Argument {
 
         Ok(())
     }
+
+    /// the highest query rate of the run, None if it is unlimited
+    pub fn peak_qps(&self) -> Option<f64> {
+        if self.capacity_search.is_some() {
+            return Some(self.search_max as f64);
+        }
+        match (&self.arrival, &self.rate_profile) {
+            _ if self.closed_loop => None,
+            (Arrival::Burst(size, period), _) => Some(*size as f64 / period),
+            (_, Some(profile)) => Some(profile.peak()),
+            _ if self.qps == 0 => None,
+            _ => Some(self.qps as f64),
+        }
+    }
+
+    /// the run above --unlisted-qps is only allowed to the servers in the
+    /// allow-list unless the user confirms owning them.
+    pub fn check_target(&self, peak_qps: Option<f64>) -> Result<(), String> {
+        if self.i_own_this_target
+            || matches!(peak_qps, Some(qps) if qps <= self.unlisted_qps as f64)
+        {
+            return Ok(());
+        }
+        let allow_list = if self.allow_list.is_empty() {
+            AllowList::default()
+        } else {
+            AllowList::from_file(self.allow_list.as_str())?
+        };
+        let mut targets = vec![(self.server.as_str(), self.port)];
+        if self.mode == Mode::Compare {
+            targets.push((self.compare_server.as_str(), self.compare_port));
+        }
+        for (server, port) in targets {
+            if let Err(reason) = allow_list.check(server, port) {
+                return Err(format!(
+                    "{}, sending {} to it needs --i-own-this-target or the network in --allow-list",
+                    reason,
+                    match peak_qps {
+                        Some(qps) => format!("{} qps", qps),
+                        None => "unlimited qps".to_string(),
+                    }
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// the queries are raw wire messages set by --raw or --raw-file
+    pub fn raw_input(&self) -> bool {
+        !self.raw.is_empty() || self.raw_file
+    }
+
+    /// the worker only decode the header of response by default, some of the
+    /// features need read the whole message, for example the edns options.
+    pub fn parse_full_response(&self) -> bool {
+        self.enable_cookie
+            || self.edns_padding > 0
+            || self.nsid
+            || self.tcp_keepalive
+            || !self.edns_option.is_empty()
+            || self.tsig.is_some()
+            || self.enable_0x20
+            || self.trust_anchor.is_some()
+            || self.ede
+    }
 }
 
 impl Default for Argument {
@@ -268,27 +911,76 @@ impl Default for Argument {
             port: 53,
             file: "".to_string(),
             fileloop: false,
+            raw: vec![],
+            raw_file: false,
+            keep_id: false,
+            random_id: false,
             protocol: Default::default(),
+            mode: Default::default(),
+            zone: "".to_string(),
+            update_template: "snd-{n} 300 A 192.0.2.1".to_string(),
+            update_records: 100,
+            ixfr_serial: 0,
+            transfer_interval: 0,
+            notify_serial: None,
+            compare_server: "".to_string(),
+            compare_port: 53,
             qps: 10,
+            rate_profile: None,
+            rate_schedule: "".to_string(),
+            arrival: Default::default(),
+            rate_phase: 0.0,
+            rate_share: 1.0,
+            capacity_search: None,
+            search_min: 100,
+            search_max: 10000,
+            search_step: 100,
+            step_time: 10,
+            max_loss: 1.0,
+            max_timeout: 1.0,
+            max_p99: 0.0,
+            abort_on_loss: 0.0,
+            abort_on_servfail: 0.0,
+            abort_no_response: 0,
+            abort_window: 10,
             max: 100,
             client: 1,
+            max_inflight: 0,
+            closed_loop: false,
             domain: "google.com".to_string(),
             qty: "NS".to_string(),
             timeout: 5,
             until_stop: 0,
+            warmup: 0,
             packet_id: 0,
             doh_server_method: Default::default(),
             doh_server: "".to_string(),
             disable_rd: false,
             enable_cd: false,
             enable_dnssec: false,
+            enable_cookie: false,
+            enable_0x20: false,
             disable_edns: false,
-            edns_size: 0,
+            edns_size: 1232,
+            edns_padding: 0,
+            edns_option: vec![],
+            nsid: false,
+            tcp_keepalive: false,
+            ede: false,
+            tsig: None,
+            tsig_file: "".to_string(),
+            dnssec_anchor: "".to_string(),
+            trust_anchor: None,
             debug: false,
             source: IpAddr::from_str("0.0.0.0").unwrap(),
             bind_cpu: "random".to_string(),
             output: "stdout".to_string(),
             output_interval: 0,
+            allow_list: "".to_string(),
+            unlisted_qps: 100,
+            i_own_this_target: false,
+            control_socket: "".to_string(),
+            control: None,
         }
     }
 }
@@ -305,6 +997,7 @@ Version: {}
         Query Type: {}
             Server: {}
 Transport Protocol: {:?}
+              Mode: {:?}
      Client Number: {}
   Query Per Second: {}
  Max Packet Number: {},
@@ -315,23 +1008,44 @@ Transport Protocol: {:?}
     Turn On CD Bit: {},
        Enable EDNS: {},
          EDNS Size: {},
+      EDNS Options: {},
      Enable DNSSEC: {},
+     Enable Cookie: {},
+       Enable 0x20: {},
+              TSIG: {},
+     DNSSEC Anchor: {},
      Bind CPU Mode: {},
             Output: {},
           Interval: {:?}\n",
             env!("CARGO_PKG_VERSION"),
             {
-                if self.file.is_empty() {
+                if self.mode != Mode::Query
+                    && self.mode != Mode::Fuzz
+                    && self.mode != Mode::Compare
+                    && !self.zone.is_empty()
+                {
+                    self.zone.as_str()
+                } else if !self.raw.is_empty() && self.file.is_empty() {
+                    "raw wire message"
+                } else if self.file.is_empty() {
                     self.domain.as_str()
                 } else {
                     self.file.as_str()
                 }
             },
             {
-                if self.file.is_empty() == false {
-                    "from query file"
-                } else {
-                    self.qty.as_str()
+                match self.mode {
+                    Mode::Update => self.update_template.as_str(),
+                    Mode::Axfr => "AXFR",
+                    Mode::Ixfr => "IXFR",
+                    Mode::Notify => "SOA",
+                    Mode::Query | Mode::Fuzz | Mode::Compare if self.raw_input() => {
+                        "from raw message"
+                    }
+                    Mode::Query | Mode::Fuzz | Mode::Compare if !self.file.is_empty() => {
+                        "from query file"
+                    }
+                    Mode::Query | Mode::Fuzz | Mode::Compare => self.qty.as_str(),
                 }
             },
             {
@@ -339,36 +1053,106 @@ Transport Protocol: {:?}
                     Protocol::DOH => {
                         format!("{}[{:?}]", self.doh_server.clone(), self.doh_server_method)
                     }
+                    _ if self.mode == Mode::Compare => format!(
+                        "{}/{} <-> {}/{}",
+                        self.server, self.port, self.compare_server, self.compare_port
+                    ),
                     _ => format!("{}/{}", self.server.clone(), self.port),
                 }
             },
             self.protocol,
-            self.client,
+            self.mode,
+            {
+                if self.max_inflight > 0 {
+                    format!("{} ({} in flight at most)", self.client, self.max_inflight)
+                } else {
+                    format!("{}", self.client)
+                }
+            },
             {
-                match self.qps {
+                let rate = match self.qps {
+                    _ if self.closed_loop => "closed loop".to_owned(),
+                    _ if matches!(self.arrival, Arrival::Burst(..)) => self.arrival.to_string(),
+                    _ if self.capacity_search.is_some() => format!(
+                        "{} search {}-{} step {} every {}s",
+                        self.capacity_search.as_ref().unwrap(),
+                        self.search_min,
+                        self.search_max,
+                        self.search_step,
+                        self.step_time
+                    ),
+                    _ if self.rate_profile.is_some() => {
+                        self.rate_profile.as_ref().unwrap().to_string()
+                    }
                     0 => "unlimited".to_owned(),
                     _ => format!("{}", self.qps),
+                };
+                if self.arrival == Arrival::Poisson {
+                    format!("{} (poisson)", rate)
+                } else {
+                    rate
                 }
             },
             self.max,
             {
-                if self.until_stop == 0 {
+                let until_stop = if self.until_stop == 0 {
                     "unlimited".to_owned()
                 } else {
                     format!("{}s", self.until_stop)
+                };
+                if self.warmup > 0 {
+                    format!("{} after {}s warmup", until_stop, self.warmup)
+                } else {
+                    until_stop
                 }
             },
             {
                 match self.packet_id {
-                    0 => "random".to_owned(),
+                    _ if self.keep_id => "keep".to_owned(),
+                    0 if self.random_id => "random".to_owned(),
+                    0 => "sequential".to_owned(),
                     _ => format!("{}", self.packet_id),
                 }
             },
             !self.disable_rd,
             self.enable_cd,
-            self.disable_edns,
+            !self.disable_edns,
             self.edns_size,
+            {
+                let mut options = vec![];
+                if self.nsid {
+                    options.push("nsid".to_owned());
+                }
+                if self.tcp_keepalive {
+                    options.push("keepalive".to_owned());
+                }
+                if self.edns_padding > 0 {
+                    options.push(format!("padding={}", self.edns_padding));
+                }
+                for option in self.edns_option.iter() {
+                    options.push(option.to_string());
+                }
+                if options.is_empty() {
+                    "none".to_owned()
+                } else {
+                    options.join(",")
+                }
+            },
             self.enable_dnssec,
+            self.enable_cookie,
+            self.enable_0x20,
+            {
+                match self.tsig.as_ref() {
+                    Some(key) => key.to_string(),
+                    None => "none".to_owned(),
+                }
+            },
+            {
+                match self.trust_anchor.as_ref() {
+                    Some(anchor) => anchor.to_string(),
+                    None => "none".to_owned(),
+                }
+            },
             self.bind_cpu,
             self.output,
             std::time::Duration::from_secs(self.output_interval as u64),
@@ -0,0 +1,332 @@
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use trust_dns_client::op::Message;
+use trust_dns_client::proto::rr::dnssec::rdata::{DNSSECRData, DNSKEY, SIG};
+use trust_dns_client::proto::rr::dnssec::{Algorithm, Verifier};
+use trust_dns_client::rr::{Name, RData, Record, RecordType};
+
+// the flags of DNSKEY record (RFC 4034 section 2.1.1 and RFC 5011 section 7)
+const ZONE_KEY_FLAG: u16 = 0x0100;
+const REVOKE_FLAG: u16 = 0x0080;
+const SEP_FLAG: u16 = 0x0001;
+
+/// DnssecStatus is the result of validating a response against the trust
+/// anchor (RFC 4035 section 4.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DnssecStatus {
+    Secure,
+    // the records are out of the zones of trust anchor
+    Insecure,
+    // the signature is missing, expired or not verified
+    Bogus,
+    // the signing key is not in trust anchor or nothing can be validated
+    Indeterminate,
+}
+
+impl fmt::Display for DnssecStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            DnssecStatus::Secure => "Secure",
+            DnssecStatus::Insecure => "Insecure",
+            DnssecStatus::Bogus => "Bogus",
+            DnssecStatus::Indeterminate => "Indeterminate",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+impl DnssecStatus {
+    // the status of response is the worst status of its RRsets
+    fn severity(&self) -> u8 {
+        match self {
+            DnssecStatus::Secure => 0,
+            DnssecStatus::Insecure => 1,
+            DnssecStatus::Indeterminate => 2,
+            DnssecStatus::Bogus => 3,
+        }
+    }
+}
+
+/// TrustAnchor is the set of trusted DNSKEY records loaded from a local key
+/// file, so the responses are validated without querying any key from the
+/// network, the signing keys of the zones must be in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustAnchor {
+    // owner name, key tag and the key
+    keys: Vec<(Name, u16, DNSKEY)>,
+}
+
+impl FromStr for TrustAnchor {
+    type Err = String;
+    /// parse the DNSKEY records in zone file format, for example the
+    /// K*.key file of dnssec-keygen or the DNSKEY answers of dig, the
+    /// other records and the comments are ignored.
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let mut keys = vec![];
+        let mut record = String::new();
+        let mut depth = 0;
+        for line in content.lines() {
+            let line = line.split(';').next().unwrap_or_default();
+            depth += line.matches('(').count() as i32 - line.matches(')').count() as i32;
+            record.push(' ');
+            record.push_str(&line.replace(['(', ')'], " "));
+            if depth > 0 {
+                continue;
+            }
+            if let Some(key) = parse_dnskey(&record)? {
+                keys.push(key);
+            }
+            record.clear();
+            depth = 0;
+        }
+        if keys.is_empty() {
+            return Err("no DNSKEY record found in trust anchor".to_string());
+        }
+        Ok(TrustAnchor { keys })
+    }
+}
+
+fn parse_dnskey(record: &str) -> Result<Option<(Name, u16, DNSKEY)>, String> {
+    let tokens: Vec<&str> = record.split_whitespace().collect();
+    let index = match tokens.iter().position(|t| t.eq_ignore_ascii_case("DNSKEY")) {
+        Some(v) if v > 0 && tokens.len() > v + 4 => v,
+        Some(_) => return Err(format!("DNSKEY record {} not valid", record.trim())),
+        None => return Ok(None),
+    };
+    let name = Name::from_ascii(tokens[0])
+        .map_err(|e| format!("DNSKEY owner {} not valid: {}", tokens[0], e))?
+        .append_domain(&Name::root());
+    let flags = tokens[index + 1]
+        .parse::<u16>()
+        .map_err(|e| format!("DNSKEY flags {} not valid: {}", tokens[index + 1], e))?;
+    let algorithm = tokens[index + 3]
+        .parse::<u8>()
+        .map(Algorithm::from_u8)
+        .map_err(|e| format!("DNSKEY algorithm {} not valid: {}", tokens[index + 3], e))?;
+    let public_key = base64::decode(tokens[index + 4..].concat())
+        .map_err(|e| format!("DNSKEY {} public key not valid: {}", name, e))?;
+    let key = DNSKEY::new(
+        flags & ZONE_KEY_FLAG != 0,
+        flags & SEP_FLAG != 0,
+        flags & REVOKE_FLAG != 0,
+        algorithm,
+        public_key,
+    );
+    let tag = key
+        .calculate_key_tag()
+        .map_err(|e| format!("DNSKEY {} key tag fail: {}", name, e))?;
+    Ok(Some((name, tag, key)))
+}
+
+impl fmt::Display for TrustAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(name, tag, _)| format!("{}/{}", name, tag))
+            .collect();
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+impl TrustAnchor {
+    pub fn from_file(file: &str) -> Result<TrustAnchor, String> {
+        fs::read_to_string(file)
+            .map_err(|e| format!("read trust anchor file {} fail: {}", file, e))?
+            .parse()
+    }
+
+    // the response is in the zones of trust anchor
+    fn covers(&self, name: &Name) -> bool {
+        self.keys.iter().any(|(owner, _, _)| owner.zone_of(name))
+    }
+
+    fn verify(&self, records: &[&Record], rrsig: &SIG, now: u32) -> DnssecStatus {
+        let keys: Vec<_> = self
+            .keys
+            .iter()
+            .filter(|(owner, tag, key)| {
+                owner == rrsig.signer_name()
+                    && *tag == rrsig.key_tag()
+                    && key.algorithm() == rrsig.algorithm()
+                    && key.zone_key()
+                    && !key.revoke()
+            })
+            .collect();
+        if keys.is_empty() {
+            return DnssecStatus::Indeterminate;
+        }
+        if now < rrsig.sig_inception() || now > rrsig.sig_expiration() {
+            return DnssecStatus::Bogus;
+        }
+        let rrset: Vec<Record> = records.iter().map(|r| (*r).clone()).collect();
+        let verified = keys.iter().any(|(_, _, key)| {
+            key.verify_rrsig(rrset[0].name(), rrset[0].dns_class(), rrsig, &rrset)
+                .is_ok()
+        });
+        if verified {
+            DnssecStatus::Secure
+        } else {
+            DnssecStatus::Bogus
+        }
+    }
+
+    /// validate the RRsets of answer and authority section with the RRSIG
+    /// records in the response, the response is secure only if all of them
+    /// are verified. The NS records of delegation are not signed by parent
+    /// zone (RFC 4035 section 2.2) so they are not checked.
+    pub fn validate(&self, message: &Message, now: u32) -> DnssecStatus {
+        let mut rrsets: Vec<(bool, Vec<&Record>)> = vec![];
+        let mut rrsigs: Vec<(&Record, &SIG)> = vec![];
+        let sections = [(true, message.answers()), (false, message.name_servers())];
+        for (answer, records) in sections.iter() {
+            for record in records.iter() {
+                if let RData::DNSSEC(DNSSECRData::SIG(sig)) = record.rdata() {
+                    rrsigs.push((record, sig));
+                    continue;
+                }
+                match rrsets.iter_mut().find(|(_, rrset)| {
+                    rrset[0].name() == record.name()
+                        && rrset[0].rr_type() == record.rr_type()
+                        && rrset[0].dns_class() == record.dns_class()
+                }) {
+                    Some((_, rrset)) => rrset.push(record),
+                    None => rrsets.push((*answer, vec![record])),
+                }
+            }
+        }
+        if rrsets.is_empty() {
+            return DnssecStatus::Indeterminate;
+        }
+        let mut result = DnssecStatus::Secure;
+        for (answer, rrset) in rrsets.iter() {
+            let name = rrset[0].name();
+            let status = if !self.covers(name) {
+                DnssecStatus::Insecure
+            } else {
+                let covering: Vec<_> = rrsigs
+                    .iter()
+                    .filter(|(record, sig)| {
+                        record.name() == name && sig.type_covered() == rrset[0].rr_type()
+                    })
+                    .collect();
+                if covering.is_empty() {
+                    let delegation = !answer
+                        && rrset[0].rr_type() == RecordType::NS
+                        && !self.keys.iter().any(|(owner, _, _)| owner == name);
+                    if delegation {
+                        continue;
+                    }
+                    DnssecStatus::Bogus
+                } else {
+                    // any of the signatures verified is enough
+                    covering
+                        .iter()
+                        .map(|(_, sig)| self.verify(rrset, sig, now))
+                        .min_by_key(|status| match status {
+                            DnssecStatus::Secure => 0,
+                            DnssecStatus::Indeterminate => 1,
+                            _ => 2,
+                        })
+                        .unwrap_or(DnssecStatus::Bogus)
+                }
+            };
+            if status.severity() > result.severity() {
+                result = status;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::dnssec::{DnssecStatus, TrustAnchor};
+    use trust_dns_client::op::Message;
+    use trust_dns_client::proto::rr::dnssec::rdata::{DNSSECRData, SIG};
+    use trust_dns_client::proto::rr::dnssec::{tbs, Algorithm};
+    use trust_dns_client::rr::dnssec::{KeyFormat, KeyPair, Private};
+    use trust_dns_client::rr::rdata::DNSSECRecordType;
+    use trust_dns_client::rr::{DNSClass, Name, RData, Record, RecordType};
+
+    const NOW: u32 = 1_600_000_000;
+
+    fn rrsig(key: &KeyPair<Private>, tag: u16, records: &[Record], expiration: u32) -> Record {
+        let record = &records[0];
+        let sig = SIG::new(
+            record.rr_type(),
+            Algorithm::ED25519,
+            record.name().num_labels(),
+            record.ttl(),
+            expiration,
+            NOW - 3600,
+            tag,
+            Name::from_ascii("example.com.").unwrap(),
+            vec![],
+        );
+        let hash = tbs::rrset_tbs_with_sig(record.name(), DNSClass::IN, &sig, records).unwrap();
+        let sig = sig.set_sig(key.sign(Algorithm::ED25519, &hash).unwrap());
+        let mut rrsig = Record::from_rdata(
+            record.name().clone(),
+            record.ttl(),
+            RData::DNSSEC(DNSSECRData::SIG(sig)),
+        );
+        rrsig.set_rr_type(RecordType::DNSSEC(DNSSECRecordType::RRSIG));
+        rrsig
+    }
+
+    #[test]
+    fn test_validate() {
+        let pkcs8 = KeyPair::<Private>::generate_pkcs8(Algorithm::ED25519).unwrap();
+        let key = KeyFormat::Pkcs8
+            .decode_key(&pkcs8, None, Algorithm::ED25519)
+            .unwrap();
+        let public = base64::encode(key.to_public_bytes().unwrap());
+        let content = format!(
+            "; This is a zone-signing key\nexample.com. 3600 IN DNSKEY 256 3 15 (\n {} {} )\n",
+            &public[..20],
+            &public[20..]
+        );
+        let anchor: TrustAnchor = content.parse().unwrap();
+        let tag = anchor.keys[0].1;
+        assert!("example.com. 3600 IN A 192.0.2.1"
+            .parse::<TrustAnchor>()
+            .is_err());
+
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        let records = vec![
+            Record::from_rdata(name.clone(), 300, RData::A("192.0.2.1".parse().unwrap())),
+            Record::from_rdata(name.clone(), 300, RData::A("192.0.2.2".parse().unwrap())),
+        ];
+        let mut message = Message::new();
+        message.add_answers(records.clone());
+        assert_eq!(anchor.validate(&message, NOW), DnssecStatus::Bogus);
+        message.add_answer(rrsig(&key, tag, &records, NOW + 3600));
+        assert_eq!(anchor.validate(&message, NOW), DnssecStatus::Secure);
+        assert_eq!(anchor.validate(&message, NOW + 7200), DnssecStatus::Bogus);
+
+        // the record is changed after signed
+        let mut message = Message::new();
+        message.add_answer(records[0].clone());
+        message.add_answer(rrsig(&key, tag, &records, NOW + 3600));
+        assert_eq!(anchor.validate(&message, NOW), DnssecStatus::Bogus);
+
+        let mut message = Message::new();
+        message.add_answers(records.clone());
+        message.add_answer(rrsig(&key, tag + 1, &records, NOW + 3600));
+        assert_eq!(anchor.validate(&message, NOW), DnssecStatus::Indeterminate);
+
+        let mut message = Message::new();
+        message.add_answer(Record::from_rdata(
+            Name::from_ascii("www.example.org.").unwrap(),
+            300,
+            RData::A("192.0.2.1".parse().unwrap()),
+        ));
+        assert_eq!(anchor.validate(&message, NOW), DnssecStatus::Insecure);
+        assert_eq!(
+            anchor.validate(&Message::new(), NOW),
+            DnssecStatus::Indeterminate
+        );
+    }
+}
@@ -1,3 +1,7 @@
+pub mod allowlist;
 pub mod arguments;
+pub mod dnssec;
+pub mod tsig;
 pub mod utils;
-pub use arguments::{Argument, DoHMethod, Protocol};
+pub mod wire;
+pub use arguments::{Argument, DoHMethod, EdnsOpt, Mode, Protocol};
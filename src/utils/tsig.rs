@@ -0,0 +1,434 @@
+use crate::utils::wire::{find_last_record, skip_name};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use trust_dns_client::rr::Name;
+
+const TSIG_TYPE: u16 = 250;
+const ANY_CLASS: u16 = 255;
+// the recommended fudge value (RFC 8945 section 10)
+const DEFAULT_FUDGE: u16 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TsigAlgorithm {
+    HmacMd5,
+    HmacSha1,
+    HmacSha224,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl FromStr for TsigAlgorithm {
+    type Err = String;
+    fn from_str(algorithm: &str) -> Result<Self, Self::Err> {
+        match algorithm.to_lowercase().trim_end_matches('.') {
+            "hmac-md5" | "hmac-md5.sig-alg.reg.int" => Ok(TsigAlgorithm::HmacMd5),
+            "hmac-sha1" => Ok(TsigAlgorithm::HmacSha1),
+            "hmac-sha224" => Ok(TsigAlgorithm::HmacSha224),
+            "hmac-sha256" => Ok(TsigAlgorithm::HmacSha256),
+            "hmac-sha384" => Ok(TsigAlgorithm::HmacSha384),
+            "hmac-sha512" => Ok(TsigAlgorithm::HmacSha512),
+            _ => Err(format!("tsig algorithm {} not valid", algorithm)),
+        }
+    }
+}
+
+impl fmt::Display for TsigAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TsigAlgorithm::HmacMd5 => "hmac-md5.sig-alg.reg.int",
+            TsigAlgorithm::HmacSha1 => "hmac-sha1",
+            TsigAlgorithm::HmacSha224 => "hmac-sha224",
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha384 => "hmac-sha384",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn compute<M: Mac + KeyInit>(secret: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = <M as KeyInit>::new_from_slice(secret).expect("hmac accepts any key size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl TsigAlgorithm {
+    fn mac_size(&self) -> usize {
+        match self {
+            TsigAlgorithm::HmacMd5 => 16,
+            TsigAlgorithm::HmacSha1 => 20,
+            TsigAlgorithm::HmacSha224 => 28,
+            TsigAlgorithm::HmacSha256 => 32,
+            TsigAlgorithm::HmacSha384 => 48,
+            TsigAlgorithm::HmacSha512 => 64,
+        }
+    }
+
+    fn mac(&self, secret: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacMd5 => compute::<Hmac<Md5>>(secret, parts),
+            TsigAlgorithm::HmacSha1 => compute::<Hmac<Sha1>>(secret, parts),
+            TsigAlgorithm::HmacSha224 => compute::<Hmac<Sha224>>(secret, parts),
+            TsigAlgorithm::HmacSha256 => compute::<Hmac<Sha256>>(secret, parts),
+            TsigAlgorithm::HmacSha384 => compute::<Hmac<Sha384>>(secret, parts),
+            TsigAlgorithm::HmacSha512 => compute::<Hmac<Sha512>>(secret, parts),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TsigStatus {
+    Verified,
+    Unsigned,
+    BadSig,
+    BadTime,
+    Rejected,
+}
+
+impl fmt::Display for TsigStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            TsigStatus::Verified => "Verified",
+            TsigStatus::Unsigned => "Unsigned",
+            TsigStatus::BadSig => "BadSig",
+            TsigStatus::BadTime => "BadTime",
+            TsigStatus::Rejected => "Rejected",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// TsigKey signs the query and verifies the response with the transaction
+/// signature (RFC 8945), the key is set by "name:algorithm:secret" or loaded
+/// from a BIND style key file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl FromStr for TsigKey {
+    type Err = String;
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let items: Vec<&str> = key.splitn(3, ':').collect();
+        if items.len() != 3 {
+            return Err(format!("tsig key {} should be name:algorithm:secret", key));
+        }
+        TsigKey::new(items[0], items[1], items[2])
+    }
+}
+
+impl fmt::Display for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.name, self.algorithm)
+    }
+}
+
+impl TsigKey {
+    pub fn new(name: &str, algorithm: &str, secret: &str) -> Result<TsigKey, String> {
+        if let Err(e) = Name::from_ascii(name) {
+            return Err(format!("tsig key name {} not valid: {}", name, e));
+        }
+        let secret = base64::decode(secret)
+            .map_err(|e| format!("tsig key {} secret not valid: {}", name, e))?;
+        Ok(TsigKey {
+            name: name.trim_end_matches('.').to_lowercase(),
+            algorithm: algorithm.parse()?,
+            secret,
+        })
+    }
+
+    /// load the first key from a BIND style key file, for example:
+    /// key "example-key" {
+    ///     algorithm hmac-sha256;
+    ///     secret "c2VjcmV0";
+    /// };
+    pub fn from_file(file: &str) -> Result<TsigKey, String> {
+        let content =
+            fs::read_to_string(file).map_err(|e| format!("read tsig file {} fail: {}", file, e))?;
+        let tokens = tokenize(&content);
+        let mut name = None;
+        let mut algorithm = None;
+        let mut secret = None;
+        let mut iter = tokens.iter().peekable();
+        while let Some(token) = iter.next() {
+            match token.as_str() {
+                "key" if name.is_none() => name = iter.next().cloned(),
+                "algorithm" if algorithm.is_none() => algorithm = iter.next().cloned(),
+                "secret" if secret.is_none() => secret = iter.next().cloned(),
+                "}" if name.is_some() => break,
+                _ => {}
+            }
+        }
+        match (name, algorithm, secret) {
+            (Some(name), Some(algorithm), Some(secret)) => TsigKey::new(&name, &algorithm, &secret),
+            _ => Err(format!("no valid tsig key found in {}", file)),
+        }
+    }
+
+    /// the tsig variables for calculating the mac (RFC 8945 section 4.3.3)
+    fn variables(&self, time: u64, fudge: u16, error: u16, other: &[u8]) -> Vec<u8> {
+        let mut variables = name_to_wire(&self.name);
+        variables.extend_from_slice(&ANY_CLASS.to_be_bytes());
+        variables.extend_from_slice(&0u32.to_be_bytes());
+        variables.extend_from_slice(&name_to_wire(&self.algorithm.to_string()));
+        variables.extend_from_slice(&time.to_be_bytes()[2..]);
+        variables.extend_from_slice(&fudge.to_be_bytes());
+        variables.extend_from_slice(&error.to_be_bytes());
+        variables.extend_from_slice(&(other.len() as u16).to_be_bytes());
+        variables.extend_from_slice(other);
+        variables
+    }
+
+    /// sign the message by appending the TSIG record, return the mac which is
+    /// needed to verify the response.
+    pub fn sign(&self, message: &mut Vec<u8>, time: u64) -> Vec<u8> {
+        let mac = self.algorithm.mac(
+            &self.secret,
+            &[message, &self.variables(time, DEFAULT_FUDGE, 0, &[])],
+        );
+        self.append_record(message, time, &mac);
+        mac
+    }
+
+    /// the size of TSIG record appended by sign
+    pub fn record_size(&self) -> usize {
+        // time 6, fudge 2, mac size 2, original id 2, error 2, other len 2
+        let rdata =
+            name_to_wire(&self.algorithm.to_string()).len() + 16 + self.algorithm.mac_size();
+        // type 2, class 2, ttl 4, rdata len 2
+        name_to_wire(&self.name).len() + 10 + rdata
+    }
+
+    fn append_record(&self, message: &mut Vec<u8>, time: u64, mac: &[u8]) {
+        let mut rdata = name_to_wire(&self.algorithm.to_string());
+        rdata.extend_from_slice(&time.to_be_bytes()[2..]);
+        rdata.extend_from_slice(&DEFAULT_FUDGE.to_be_bytes());
+        rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(mac);
+        rdata.extend_from_slice(&message[0..2]);
+        rdata.extend_from_slice(&0u16.to_be_bytes());
+        rdata.extend_from_slice(&0u16.to_be_bytes());
+
+        message.extend_from_slice(&name_to_wire(&self.name));
+        message.extend_from_slice(&TSIG_TYPE.to_be_bytes());
+        message.extend_from_slice(&ANY_CLASS.to_be_bytes());
+        message.extend_from_slice(&0u32.to_be_bytes());
+        message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        message.extend_from_slice(&rdata);
+        let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+        message[10..12].copy_from_slice(&additional.to_be_bytes());
+    }
+
+    /// verify the response signed with the mac of request, return the status
+    /// and the error field of TSIG record.
+    pub fn verify(&self, response: &[u8], request_mac: &[u8], now: u64) -> (TsigStatus, u16) {
+        let position = match find_last_record(response) {
+            Some(v) => v,
+            None => return (TsigStatus::Unsigned, 0),
+        };
+        let rdata = match skip_name(response, position) {
+            Some(v) => v + 10,
+            None => return (TsigStatus::Unsigned, 0),
+        };
+        if u16::from_be_bytes([response[rdata - 10], response[rdata - 9]]) != TSIG_TYPE {
+            return (TsigStatus::Unsigned, 0);
+        }
+        let field = match skip_name(response, rdata) {
+            Some(v) if v + 10 <= response.len() => v,
+            _ => return (TsigStatus::BadSig, 0),
+        };
+        let read_u16 = |i: usize| u16::from_be_bytes([response[i], response[i + 1]]);
+        let mut time_bytes = [0u8; 8];
+        time_bytes[2..].copy_from_slice(&response[field..field + 6]);
+        let time = u64::from_be_bytes(time_bytes);
+        let fudge = read_u16(field + 6);
+        let mac_size = read_u16(field + 8) as usize;
+        let mac_end = field + 10 + mac_size;
+        if mac_end + 6 > response.len() {
+            return (TsigStatus::BadSig, 0);
+        }
+        let mac = &response[field + 10..mac_end];
+        let original_id = &response[mac_end..mac_end + 2];
+        let error = read_u16(mac_end + 2);
+        let other_size = read_u16(mac_end + 4) as usize;
+        if mac_end + 6 + other_size > response.len() {
+            return (TsigStatus::BadSig, error);
+        }
+        let other = &response[mac_end + 6..mac_end + 6 + other_size];
+        if error != 0 {
+            return (TsigStatus::Rejected, error);
+        }
+
+        let mut unsigned = response[..position].to_vec();
+        unsigned[0..2].copy_from_slice(original_id);
+        let additional = u16::from_be_bytes([unsigned[10], unsigned[11]]).saturating_sub(1);
+        unsigned[10..12].copy_from_slice(&additional.to_be_bytes());
+        let expected = self.algorithm.mac(
+            &self.secret,
+            &[
+                &(request_mac.len() as u16).to_be_bytes(),
+                request_mac,
+                &unsigned,
+                &self.variables(time, fudge, error, other),
+            ],
+        );
+        if expected != mac {
+            return (TsigStatus::BadSig, error);
+        }
+        if now.max(time) - now.min(time) > fudge as u64 {
+            return (TsigStatus::BadTime, error);
+        }
+        (TsigStatus::Verified, error)
+    }
+}
+
+fn name_to_wire(name: &str) -> Vec<u8> {
+    let mut wire = vec![];
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() {
+            continue;
+        }
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label.to_lowercase().as_bytes());
+    }
+    wire.push(0);
+    wire
+}
+
+// split the BIND config into tokens, quoted string is one token
+fn tokenize(content: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        let mut current = String::new();
+        let mut quoted = false;
+        for c in line.chars() {
+            match c {
+                '"' => {
+                    if quoted {
+                        tokens.push(current.clone());
+                        current.clear();
+                    }
+                    quoted = !quoted;
+                }
+                _ if quoted => current.push(c),
+                '{' | '}' | ';' => {
+                    if !current.is_empty() {
+                        tokens.push(current.clone());
+                        current.clear();
+                    }
+                    tokens.push(c.to_string());
+                }
+                _ if c.is_whitespace() => {
+                    if !current.is_empty() {
+                        tokens.push(current.clone());
+                        current.clear();
+                    }
+                }
+                _ => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            tokens.push(current);
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::tsig::{TsigAlgorithm, TsigKey, TsigStatus};
+    use std::io::Write;
+
+    fn query() -> Vec<u8> {
+        vec![
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0x65,
+            0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x01, 0x00,
+            0x01,
+        ]
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let key: TsigKey = "test-key.:hmac-sha256:c2VjcmV0".parse().unwrap();
+        assert_eq!(key.name, "test-key");
+        assert_eq!(key.algorithm, TsigAlgorithm::HmacSha256);
+        assert_eq!(key.secret, b"secret".to_vec());
+        assert!("test-key:hmac-sha256".parse::<TsigKey>().is_err());
+        assert!("test-key:hmac-sha3:c2VjcmV0".parse::<TsigKey>().is_err());
+        assert!("test-key:hmac-sha256:!!".parse::<TsigKey>().is_err());
+
+        let path = std::env::temp_dir().join("snd-test-tsig.key");
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(
+            b"key \"test-key\" {\n\talgorithm hmac-sha512;\n\tsecret \"c2VjcmV0\";\n};\n",
+        )
+        .unwrap();
+        let key = TsigKey::from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(key.name, "test-key");
+        assert_eq!(key.algorithm, TsigAlgorithm::HmacSha512);
+        assert_eq!(key.secret, b"secret".to_vec());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key: TsigKey = "test-key:hmac-sha256:c2VjcmV0".parse().unwrap();
+        let time = 1_600_000_000;
+        let mut message = query();
+        let request_mac = key.sign(&mut message, time);
+        assert_eq!(request_mac.len(), 32);
+        assert_eq!(message[11], 1);
+
+        // the response mac is prefixed with the request mac
+        let mut response = query();
+        response[2] |= 0x80;
+        let mac = key.algorithm.mac(
+            &key.secret,
+            &[
+                &(request_mac.len() as u16).to_be_bytes(),
+                &request_mac,
+                &response,
+                &key.variables(time, 300, 0, &[]),
+            ],
+        );
+        let mut signed = response.clone();
+        key.append_record(&mut signed, time, &mac);
+
+        assert_eq!(
+            key.verify(&signed, &request_mac, time + 10),
+            (TsigStatus::Verified, 0)
+        );
+        assert_eq!(
+            key.verify(&signed, &request_mac, time + 1000),
+            (TsigStatus::BadTime, 0)
+        );
+        assert_eq!(key.verify(&signed, &[0; 32], time), (TsigStatus::BadSig, 0));
+        assert_eq!(
+            key.verify(&response, &request_mac, time),
+            (TsigStatus::Unsigned, 0)
+        );
+
+        // the error field is BADKEY
+        let size = signed.len();
+        signed[size - 4..size - 2].copy_from_slice(&17u16.to_be_bytes());
+        assert_eq!(
+            key.verify(&signed, &request_mac, time),
+            (TsigStatus::Rejected, 17)
+        );
+    }
+}
@@ -34,6 +34,28 @@ pub fn cpu_mode_to_cpu_cores(cpu_mode: String) -> Result<Vec<CoreId>, Box<dyn Er
     };
 }
 
+pub fn parse_hex(value: &str) -> Result<Vec<u8>, String> {
+    value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|v| v.len() == 2)
+                .and_then(|v| u8::from_str_radix(v, 16).ok())
+                .ok_or_else(|| format!("hex string {} not valid", value))
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_hex() {
+    assert_eq!(parse_hex("").unwrap(), Vec::<u8>::new());
+    assert_eq!(parse_hex("00abFF").unwrap(), vec![0x00, 0xab, 0xff]);
+    assert!(parse_hex("abc").is_err());
+    assert!(parse_hex("zz").is_err());
+}
+
 #[test]
 fn test_cpu_mode_to_cores() {
     let cores = get_core_ids().unwrap();
@@ -0,0 +1,347 @@
+// helpers for patching the encoded dns message directly, the packets in cache
+// are prebuilt, rebuild them from Message for each query is too expensive.
+
+use crate::utils::utils::parse_hex;
+
+const HEADER_SIZE: usize = 12;
+const OPT_HEADER_SIZE: usize = 11;
+const OPT_TYPE: u16 = 41;
+pub const PADDING_CODE: u16 = 12;
+// the recommended block size for query padding (RFC 8467)
+pub const DEFAULT_PADDING_BLOCK: usize = 128;
+
+/// find the start position of the OPT record, the OPT record must be the last
+/// record of the message which is what trust-dns does when encode a message.
+pub fn find_opt_record(message: &[u8]) -> Option<usize> {
+    if message.len() < HEADER_SIZE + OPT_HEADER_SIZE {
+        return None;
+    }
+    let mut position = message.len() - OPT_HEADER_SIZE;
+    loop {
+        if message[position] == 0
+            && u16::from_be_bytes([message[position + 1], message[position + 2]]) == OPT_TYPE
+        {
+            let rdlength =
+                u16::from_be_bytes([message[position + 9], message[position + 10]]) as usize;
+            if position + OPT_HEADER_SIZE + rdlength == message.len() {
+                return Some(position);
+            }
+        }
+        if position == HEADER_SIZE {
+            return None;
+        }
+        position -= 1;
+    }
+}
+
+/// append a edns option to the end of the OPT record, return false if
+/// the message has no OPT record.
+pub fn append_edns_option(message: &mut Vec<u8>, code: u16, data: &[u8]) -> bool {
+    let position = match find_opt_record(message) {
+        Some(v) => v,
+        None => return false,
+    };
+    let rdlength = u16::from_be_bytes([message[position + 9], message[position + 10]]) as usize
+        + 4
+        + data.len();
+    message[position + 9..position + 11].copy_from_slice(&(rdlength as u16).to_be_bytes());
+    message.extend_from_slice(&code.to_be_bytes());
+    message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    message.extend_from_slice(data);
+    true
+}
+
+/// remove the edns option from the OPT record, return false if
+/// the option is not found.
+pub fn remove_edns_option(message: &mut Vec<u8>, code: u16) -> bool {
+    let position = match find_opt_record(message) {
+        Some(v) => v,
+        None => return false,
+    };
+    let mut cursor = position + OPT_HEADER_SIZE;
+    while cursor + 4 <= message.len() {
+        let option_code = u16::from_be_bytes([message[cursor], message[cursor + 1]]);
+        let option_size =
+            4 + u16::from_be_bytes([message[cursor + 2], message[cursor + 3]]) as usize;
+        if cursor + option_size > message.len() {
+            return false;
+        }
+        if option_code == code {
+            message.drain(cursor..cursor + option_size);
+            let rdlength = (message.len() - position - OPT_HEADER_SIZE) as u16;
+            message[position + 9..position + 11].copy_from_slice(&rdlength.to_be_bytes());
+            return true;
+        }
+        cursor += option_size;
+    }
+    false
+}
+
+/// pad the message to a multiple of block size with the padding option
+/// (RFC 7830), the padding option already in message will be replaced.
+/// The trailing is the size of records appended after padding, like TSIG.
+pub fn pad_message(message: &mut Vec<u8>, block: usize, trailing: usize) -> bool {
+    if block == 0 {
+        return false;
+    }
+    remove_edns_option(message, PADDING_CODE);
+    let size = message.len() + 4 + trailing;
+    let padding = (block - size % block) % block;
+    append_edns_option(message, PADDING_CODE, &vec![0; padding])
+}
+
+/// skip the domain name start from position, return the position after it.
+pub fn skip_name(message: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(position)? as usize;
+        if length == 0 {
+            return Some(position + 1);
+        }
+        // compression pointer is always the end of a name
+        if length & 0xc0 == 0xc0 {
+            return if position + 2 <= message.len() {
+                Some(position + 2)
+            } else {
+                None
+            };
+        }
+        if length & 0xc0 != 0 {
+            return None;
+        }
+        position += length + 1;
+    }
+}
+
+/// find the start position of the last resource record in message.
+pub fn find_last_record(message: &[u8]) -> Option<usize> {
+    if message.len() < HEADER_SIZE {
+        return None;
+    }
+    let count = |i: usize| u16::from_be_bytes([message[i], message[i + 1]]) as usize;
+    let mut position = HEADER_SIZE;
+    for _ in 0..count(4) {
+        position = skip_name(message, position)? + 4;
+    }
+    let mut last = None;
+    for _ in 0..count(6) + count(8) + count(10) {
+        last = Some(position);
+        let rdata = skip_name(message, position)? + 10;
+        if rdata > message.len() {
+            return None;
+        }
+        position = rdata + u16::from_be_bytes([message[rdata - 2], message[rdata - 1]]) as usize;
+    }
+    if position > message.len() {
+        return None;
+    }
+    last
+}
+
+/// the data of all the edns options with code in the OPT record, the whole
+/// message is walked since the OPT record may be followed by TSIG record.
+pub fn find_edns_options(message: &[u8], code: u16) -> Vec<&[u8]> {
+    let mut options = vec![];
+    if message.len() < HEADER_SIZE {
+        return options;
+    }
+    let count = |i: usize| u16::from_be_bytes([message[i], message[i + 1]]) as usize;
+    let mut position = HEADER_SIZE;
+    for _ in 0..count(4) {
+        position = match skip_name(message, position) {
+            Some(v) => v + 4,
+            None => return options,
+        };
+    }
+    for _ in 0..count(6) + count(8) + count(10) {
+        let rdata = match skip_name(message, position) {
+            Some(v) if v + 10 <= message.len() => v + 10,
+            _ => return options,
+        };
+        let end = rdata + count(rdata - 2);
+        if end > message.len() {
+            return options;
+        }
+        if count(rdata - 10) == OPT_TYPE as usize {
+            let mut cursor = rdata;
+            while cursor + 4 <= end {
+                let size = count(cursor + 2);
+                if cursor + 4 + size > end {
+                    break;
+                }
+                if count(cursor) == code as usize {
+                    options.push(&message[cursor + 4..cursor + 4 + size]);
+                }
+                cursor += 4 + size;
+            }
+        }
+        position = end;
+    }
+    options
+}
+
+/// the first question of message including the name, type and class,
+/// None if the message has no question or the question is truncated.
+pub fn first_question(message: &[u8]) -> Option<&[u8]> {
+    if message.len() < HEADER_SIZE || u16::from_be_bytes([message[4], message[5]]) == 0 {
+        return None;
+    }
+    let end = skip_name(message, HEADER_SIZE)? + 4;
+    message.get(HEADER_SIZE..end)
+}
+
+/// the query type of the first question, 0 if the message has no question.
+pub fn question_type(message: &[u8]) -> u16 {
+    match first_question(message) {
+        Some(question) => {
+            let position = question.len() - 4;
+            u16::from_be_bytes([question[position], question[position + 1]])
+        }
+        None => 0,
+    }
+}
+
+/// parse the raw wire message in hex or base64 with "base64:" prefix, the
+/// whitespaces and "0x" prefix of hex string are ignored.
+pub fn parse_raw_message(value: &str) -> Result<Vec<u8>, String> {
+    let value = value.trim();
+    let message = if let Some(encoded) = value.strip_prefix("base64:") {
+        base64::decode(encoded.trim())
+            .map_err(|e| format!("raw message {} not valid: {}", value, e))?
+    } else {
+        let hex: String = value
+            .trim_start_matches("0x")
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        parse_hex(hex.as_str())?
+    };
+    if message.len() < HEADER_SIZE {
+        return Err(format!("raw message {} is shorter than dns header", value));
+    }
+    Ok(message)
+}
+
+/// add the two bytes length prefix for dns over tcp message
+pub fn add_tcp_length(mut message: Vec<u8>) -> Vec<u8> {
+    let mut raw_with_size = Vec::with_capacity(message.len() + 2);
+    raw_with_size.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    raw_with_size.append(&mut message);
+    raw_with_size
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::wire::{
+        append_edns_option, find_edns_options, find_last_record, find_opt_record, pad_message,
+        parse_raw_message, question_type, remove_edns_option,
+    };
+    use trust_dns_client::proto::op::{Edns, Message, Query};
+    use trust_dns_client::proto::rr::rdata::opt::EdnsCode;
+    use trust_dns_client::proto::rr::{Name, RecordType};
+
+    #[test]
+    fn test_append_edns_option() {
+        let mut message = Message::new();
+        message.add_query(Query::default());
+        let mut raw = message.to_vec().unwrap();
+        assert_eq!(find_opt_record(&raw), None);
+        assert!(!append_edns_option(&mut raw, 10, &[1, 2, 3]));
+
+        message.set_edns(Edns::new());
+        let mut raw = message.to_vec().unwrap();
+        assert_eq!(find_opt_record(&raw), Some(raw.len() - 11));
+        assert!(append_edns_option(&mut raw, 10, &[1; 8]));
+        assert!(append_edns_option(&mut raw, 65001, &[0xab, 0xcd]));
+
+        let decoded = Message::from_vec(&raw).unwrap();
+        let edns = decoded.edns().unwrap();
+        assert_eq!(edns.options().as_ref().len(), 2);
+        assert!(edns.option(EdnsCode::Cookie).is_some());
+        assert!(edns.option(EdnsCode::Unknown(65001)).is_some());
+
+        assert!(remove_edns_option(&mut raw, 10));
+        assert!(!remove_edns_option(&mut raw, 10));
+        let decoded = Message::from_vec(&raw).unwrap();
+        let edns = decoded.edns().unwrap();
+        assert_eq!(edns.options().as_ref().len(), 1);
+        assert!(edns.option(EdnsCode::Cookie).is_none());
+    }
+
+    #[test]
+    fn test_find_last_record() {
+        let mut message = Message::new();
+        message.add_query(Query::default());
+        let raw = message.to_vec().unwrap();
+        assert_eq!(find_last_record(&raw), None);
+        message.set_edns(Edns::new());
+        let raw = message.to_vec().unwrap();
+        assert_eq!(find_last_record(&raw), find_opt_record(&raw));
+        assert_eq!(find_last_record(&raw[..raw.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_find_edns_options() {
+        let mut message = Message::new();
+        message.add_query(Query::default());
+        message.set_edns(Edns::new());
+        let mut raw = message.to_vec().unwrap();
+        assert!(find_edns_options(&raw, 15).is_empty());
+        append_edns_option(&mut raw, 15, &[0, 18]);
+        append_edns_option(&mut raw, 10, &[1; 8]);
+        append_edns_option(&mut raw, 15, &[0, 3, b's']);
+        // a record after the OPT record
+        raw.extend_from_slice(&[0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 192, 0, 2, 1]);
+        raw[11] += 1;
+        assert_eq!(
+            find_edns_options(&raw, 15),
+            vec![&[0, 18][..], &[0, 3, b's'][..]]
+        );
+        assert!(find_edns_options(&raw[..raw.len() - 16], 15).is_empty());
+    }
+
+    #[test]
+    fn test_pad_message() {
+        let mut message = Message::new();
+        message.add_query(Query::default());
+        message.set_edns(Edns::new());
+        let mut raw = message.to_vec().unwrap();
+        assert!(pad_message(&mut raw, 128, 0));
+        assert_eq!(raw.len(), 128);
+        assert!(append_edns_option(&mut raw, 3, &[]));
+        assert!(pad_message(&mut raw, 64, 0));
+        assert_eq!(raw.len(), 64);
+        assert!(pad_message(&mut raw, 468, 0));
+        assert_eq!(raw.len(), 468);
+        assert!(pad_message(&mut raw, 128, 20));
+        assert_eq!(raw.len(), 108);
+
+        let decoded = Message::from_vec(&raw).unwrap();
+        let edns = decoded.edns().unwrap();
+        assert_eq!(edns.options().as_ref().len(), 2);
+        assert!(edns.option(EdnsCode::Padding).is_some());
+    }
+
+    #[test]
+    fn test_parse_raw_message() {
+        let mut message = Message::new();
+        message.add_query(Query::query(
+            Name::from_ascii("example.com.").unwrap(),
+            RecordType::MX,
+        ));
+        let raw = message.to_vec().unwrap();
+        let hex: String = raw.iter().map(|b| format!("{:02x} ", b)).collect();
+        assert_eq!(parse_raw_message(hex.as_str()).unwrap(), raw);
+        assert_eq!(
+            parse_raw_message(&format!("0x{}", hex.replace(' ', ""))).unwrap(),
+            raw
+        );
+        let encoded = format!("base64:{}", base64::encode(&raw));
+        assert_eq!(parse_raw_message(encoded.as_str()).unwrap(), raw);
+        assert_eq!(question_type(&raw), u16::from(RecordType::MX));
+        assert!(parse_raw_message("00010000").is_err());
+        assert!(parse_raw_message("base64:!!").is_err());
+
+        message.take_queries();
+        assert_eq!(question_type(&message.to_vec().unwrap()), 0);
+    }
+}
@@ -0,0 +1,128 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// IdAllocator gives out the message id for the queries of one socket, the
+/// id of an outstanding query is never reissued until it is answered or
+/// older than the timeout.
+pub struct IdAllocator {
+    random: bool,
+    next: u16,
+    timeout: Duration,
+    // id to the question of query and the time it was sent
+    outstanding: HashMap<u16, (Vec<u8>, Instant)>,
+}
+
+impl IdAllocator {
+    pub fn new(random: bool, timeout: Duration) -> IdAllocator {
+        IdAllocator {
+            random,
+            next: 0,
+            timeout,
+            outstanding: HashMap::new(),
+        }
+    }
+
+    fn candidate(&mut self) -> u16 {
+        if self.random {
+            rand::thread_rng().gen()
+        } else {
+            let id = self.next;
+            self.next = self.next.wrapping_add(1);
+            id
+        }
+    }
+
+    /// allocate the id for the query with question, the question is checked
+    /// against the response later.
+    pub fn allocate(&mut self, question: &[u8]) -> u16 {
+        let now = Instant::now();
+        let mut id = self.candidate();
+        for _ in 0..u16::MAX {
+            match self.outstanding.get(&id) {
+                Some((_, sent)) if now.duration_since(*sent) < self.timeout => {
+                    id = self.candidate()
+                }
+                _ => break,
+            }
+        }
+        self.outstanding.insert(id, (question.to_vec(), now));
+        id
+    }
+
+    /// release the id of the query answered by the response, return false if
+    /// the response matches no outstanding query. The response without
+    /// question section (for example FORMERR) is matched by id only, the
+    /// question is compared case-insensitively because of dns-0x20.
+    pub fn release(&mut self, id: u16, question: Option<&[u8]>) -> bool {
+        let matched = match (self.outstanding.get(&id), question) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some((expected, _)), Some(question)) => expected.eq_ignore_ascii_case(question),
+        };
+        if matched {
+            self.outstanding.remove(&id);
+        }
+        matched
+    }
+
+    /// forget the queries older than the timeout, their responses are
+    /// unexpected from now on.
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        let timeout = self.timeout;
+        self.outstanding
+            .retain(|_, (_, sent)| now.duration_since(*sent) < timeout);
+    }
+
+    /// whether the query with id is still waiting for response
+    pub fn is_outstanding(&self, id: u16) -> bool {
+        self.outstanding.contains_key(&id)
+    }
+
+    /// the number of queries waiting for response
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::workers::allocator::IdAllocator;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    #[test]
+    fn test_id_allocator() {
+        let mut allocator = IdAllocator::new(false, Duration::from_secs(5));
+        let first = allocator.allocate(b"q1");
+        assert_eq!(first, 0);
+        // wrap around and skip the outstanding id
+        allocator.next = 0;
+        let second = allocator.allocate(b"q2");
+        assert_eq!(second, 1);
+        assert_eq!(allocator.outstanding(), 2);
+
+        assert!(!allocator.release(2, None));
+        assert!(!allocator.release(first, Some(b"q2")));
+        assert!(allocator.release(first, Some(b"Q1")));
+        assert!(!allocator.release(first, Some(b"q1")));
+        assert!(allocator.release(second, None));
+        assert_eq!(allocator.outstanding(), 0);
+
+        let mut allocator = IdAllocator::new(true, Duration::from_secs(5));
+        let ids: HashSet<u16> = (0..1000).map(|_| allocator.allocate(b"q")).collect();
+        assert_eq!(ids.len(), 1000);
+
+        // the expired query is not outstanding any more
+        let mut allocator = IdAllocator::new(false, Duration::from_secs(0));
+        allocator.allocate(b"q1");
+        allocator.next = 0;
+        assert_eq!(allocator.allocate(b"q2"), 0);
+        assert!(allocator.is_outstanding(0));
+        allocator.expire();
+        assert_eq!(allocator.outstanding(), 0);
+        assert!(!allocator.is_outstanding(0));
+        assert!(!allocator.release(0, None));
+    }
+}
@@ -0,0 +1,246 @@
+use super::session::{Inspection, Session};
+use super::{MessageOrHeader, Worker};
+use crate::runner::compare::{compare_messages, BOTH_TIMEOUT, NO_RESPONSE};
+use crate::runner::consumer::ResponseConsumer;
+use crate::runner::report::StatusStore;
+use crate::runner::{producer::PacketGeneratorStatus, QueryProducer};
+use crate::utils::{Argument, Protocol};
+use std::io::{Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::ops::Add;
+use std::time::{Duration, SystemTime};
+use trust_dns_client::op::Message;
+
+/// Connection is the blocking socket to one of the compared servers
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    fn connect(arguments: &Argument, server: &str, port: u16) -> Result<Connection, String> {
+        let server_port = format!("{}:{}", server, port);
+        match arguments.protocol {
+            Protocol::TCP => TcpStream::connect(server_port.as_str())
+                .map(Connection::Tcp)
+                .map_err(|e| format!("connect {} fail: {}", server_port, e)),
+            _ => {
+                let socket = UdpSocket::bind(format!("{}:0", arguments.source))
+                    .map_err(|e| format!("bind source ip fail: {}", e))?;
+                socket
+                    .connect(server_port.as_str())
+                    .map_err(|e| format!("connect {} fail: {}", server_port, e))?;
+                Ok(Connection::Udp(socket))
+            }
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Connection::Udp(socket) => socket.send(data).map(|_| ()),
+            Connection::Tcp(stream) => stream.write_all(data),
+        }
+    }
+
+    /// receive a dns message without the tcp length prefix
+    fn receive(&mut self, timeout: Duration) -> std::io::Result<Vec<u8>> {
+        match self {
+            Connection::Udp(socket) => {
+                socket.set_read_timeout(Some(timeout))?;
+                let mut buffer = vec![0; u16::MAX as usize];
+                let size = socket.recv(&mut buffer)?;
+                buffer.truncate(size);
+                Ok(buffer)
+            }
+            Connection::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                let mut length = [0; 2];
+                stream.read_exact(&mut length)?;
+                let mut buffer = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut buffer)?;
+                Ok(buffer)
+            }
+        }
+    }
+}
+
+/// Server is the connection and session of one compared server
+struct Server {
+    connection: Connection,
+    session: Session,
+}
+
+impl Server {
+    /// send the query and wait for the response of it
+    fn exchange(
+        &mut self,
+        data: &[u8],
+        timeout: Duration,
+        store: &mut StatusStore,
+    ) -> Option<(Message, f64, Inspection)> {
+        let packet = self.session.prepare(data);
+        let start = SystemTime::now();
+        if let Err(e) = self.connection.send(packet) {
+            debug!("send error: {}", e);
+            return None;
+        }
+        loop {
+            let elapsed = start.elapsed().unwrap_or_default();
+            if elapsed >= timeout {
+                return None;
+            }
+            let raw = match self.connection.receive(timeout - elapsed) {
+                Ok(v) => v,
+                Err(e) => {
+                    debug!("receive error: {}", e);
+                    return None;
+                }
+            };
+            if !self.session.accept(&raw) {
+                store.update_unexpected();
+                continue;
+            }
+            let duration = start.elapsed().unwrap_or_default().as_secs_f64();
+            return match Message::from_vec(&raw) {
+                Ok(message) => {
+                    let inspection = self.session.inspect(&raw, &message);
+                    Some((message, duration, inspection))
+                }
+                Err(e) => {
+                    error!("parse dns message error: {}", e);
+                    None
+                }
+            };
+        }
+    }
+}
+
+/// CompareWorker sends every query to the primary server and then the
+/// second server, and compares the responses of them.
+pub struct CompareWorker {
+    arguments: Argument,
+    servers: Option<(Server, Server)>,
+}
+
+impl Worker for CompareWorker {
+    fn run(
+        &mut self,
+        id: usize,
+        sender: crossbeam_channel::Sender<(StatusStore, StatusStore)>,
+    ) -> (StatusStore, StatusStore) {
+        let arguments = self.arguments.clone();
+        let interval = arguments.output_interval as u64;
+        let timeout = Duration::from_secs(arguments.timeout as u64);
+        let mut next_status_send = SystemTime::now().add(Duration::from_secs(interval));
+        let mut producer = QueryProducer::new(arguments.clone());
+        let mut consumer = ResponseConsumer::new();
+        consumer.set_expectations(producer.expectations().clone());
+        let mut send_counter: u64 = 0;
+        let mut receive_counter: u64 = 0;
+        let start = SystemTime::now();
+        // the tcp query starts with the 2 bytes length
+        let offset = match arguments.protocol {
+            Protocol::TCP => 2,
+            _ => 0,
+        };
+        let (primary, secondary) = match self.servers.as_mut() {
+            Some(v) => (&mut v.0, &mut v.1),
+            None => return (producer.store, consumer.store),
+        };
+        loop {
+            let (data, qtype) = match producer.retrieve() {
+                PacketGeneratorStatus::Success(data, qtype) => (data.to_vec(), qtype),
+                PacketGeneratorStatus::Wait(v) => {
+                    std::thread::sleep(Duration::from_nanos(v));
+                    continue;
+                }
+                PacketGeneratorStatus::Stop => break,
+            };
+            producer.store.update_query(qtype);
+            send_counter += 1;
+            let first = primary.exchange(&data, timeout, &mut consumer.store);
+            let second = secondary.exchange(&data, timeout, &mut consumer.store);
+            let differences = match (first.as_ref(), second.as_ref()) {
+                (Some(first), Some(second)) => compare_messages(&first.0, &second.0),
+                (Some(_), None) => vec![(NO_RESPONSE, "second server".to_string())],
+                (None, Some(_)) => vec![(NO_RESPONSE, "primary server".to_string())],
+                (None, None) => vec![(BOTH_TIMEOUT, "primary and second server".to_string())],
+            };
+            // the query is taken from the request when no server responds
+            let request = match (first.as_ref(), second.as_ref()) {
+                (None, None) => Message::from_vec(&data[offset..]).ok(),
+                _ => None,
+            };
+            let message = first.as_ref().or(second.as_ref()).map(|v| &v.0);
+            if let Some(message) = message.or(request.as_ref()) {
+                let query = message
+                    .queries()
+                    .first()
+                    .map(|q| format!("{} {}", q.name(), q.query_type()))
+                    .unwrap_or_default();
+                consumer.store.compare.update(query.as_str(), &differences);
+            }
+            if let Some((_, duration, _)) = second {
+                consumer.store.compare.secondary.update(duration);
+            }
+            if let Some((message, duration, inspection)) = first {
+                receive_counter += 1;
+                consumer.store.compare.primary.update(duration);
+                consumer.receive(&MessageOrHeader::Message((message, duration)));
+                consumer.store.update_inspection(&inspection);
+            }
+            if interval != 0 {
+                let now = SystemTime::now();
+                if now >= next_status_send {
+                    producer
+                        .store
+                        .set_send_duration(now.duration_since(start).unwrap());
+                    producer.update_interval_rate();
+                    consumer.store.set_receive_total(receive_counter);
+                    consumer.update_report();
+                    if let Err(err) = sender.send((producer.store.clone(), consumer.store.clone()))
+                    {
+                        error!("send interval status fail: {:?}", err)
+                    }
+                    next_status_send = now.add(Duration::from_secs(interval));
+                }
+            }
+        }
+        debug!(
+            "compare finished send = {} receive = {} cpu={}",
+            send_counter, receive_counter, id
+        );
+        std::mem::drop(sender);
+        producer
+            .store
+            .set_send_duration(start.elapsed().unwrap_or_default());
+        consumer.store.set_receive_total(receive_counter);
+        producer.update_total_rate();
+        consumer.receive(&MessageOrHeader::End);
+        (producer.store, consumer.store)
+    }
+}
+
+impl CompareWorker {
+    // boxed like the other workers, the runner only holds dyn Worker
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(arguments: Argument) -> Box<dyn Worker> {
+        let connect = |server: &str, port: u16| -> Result<Server, String> {
+            Ok(Server {
+                connection: Connection::connect(&arguments, server, port)?,
+                session: Session::new(&arguments),
+            })
+        };
+        let servers = match (
+            connect(arguments.server.as_str(), arguments.port),
+            connect(arguments.compare_server.as_str(), arguments.compare_port),
+        ) {
+            (Ok(primary), Ok(secondary)) => Some((primary, secondary)),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("{}", e);
+                None
+            }
+        };
+        Box::new(CompareWorker { arguments, servers })
+    }
+}
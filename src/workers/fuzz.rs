@@ -0,0 +1,174 @@
+use super::{MessageOrHeader, Worker, HEADER_SIZE};
+use crate::runner::consumer::ResponseConsumer;
+use crate::runner::fuzz::{Mutation, MutationResult, MUTATIONS};
+use crate::runner::report::StatusStore;
+use crate::runner::{producer::PacketGeneratorStatus, QueryProducer};
+use crate::utils::Argument;
+use std::net::UdpSocket;
+use std::ops::Add;
+use std::time::{Duration, SystemTime};
+use trust_dns_client::op::{Header, ResponseCode};
+use trust_dns_client::proto::serialize::binary::BinDecodable;
+
+/// FuzzWorker sends the mutated packets one by one and waits for the answer,
+/// the server may drop the malformed packet silently so a valid probe query
+/// is sent after the unanswered packet to check whether the server is alive.
+pub struct FuzzWorker {
+    arguments: Argument,
+    socket: Option<UdpSocket>,
+}
+
+impl FuzzWorker {
+    // the mutation class is kept in the highest 4 bits of message id
+    fn message_id(mutation: Mutation, counter: u64) -> u16 {
+        ((mutation as u16) << 12) | (counter % 0x1000) as u16
+    }
+
+    /// send the packet and wait for the response with the same id
+    fn exchange(socket: &UdpSocket, packet: &[u8], timeout: Duration) -> Option<(Header, f64)> {
+        let start = SystemTime::now();
+        if let Err(e) = socket.send(packet) {
+            debug!("send error: {}", e);
+            return None;
+        }
+        let mut buffer = vec![0; u16::MAX as usize];
+        loop {
+            let elapsed = start.elapsed().unwrap_or_default();
+            if elapsed >= timeout {
+                return None;
+            }
+            socket
+                .set_read_timeout(Some(timeout - elapsed))
+                .expect("set read timeout fail");
+            let size = match socket.recv(&mut buffer) {
+                Ok(v) => v,
+                Err(_) => return None,
+            };
+            // the late response of previous packet is ignored
+            if size < HEADER_SIZE || buffer[0..2] != packet[0..2] {
+                continue;
+            }
+            if let Ok(header) = Header::from_bytes(&buffer[..HEADER_SIZE]) {
+                return Some((header, start.elapsed().unwrap_or_default().as_secs_f64()));
+            }
+        }
+    }
+}
+
+impl Worker for FuzzWorker {
+    fn run(
+        &mut self,
+        id: usize,
+        sender: crossbeam_channel::Sender<(StatusStore, StatusStore)>,
+    ) -> (StatusStore, StatusStore) {
+        let arguments = self.arguments.clone();
+        let interval = arguments.output_interval as u64;
+        let timeout = Duration::from_secs(arguments.timeout as u64);
+        let mut next_status_send = SystemTime::now().add(Duration::from_secs(interval));
+        let mut producer = QueryProducer::new(arguments.clone());
+        let mut consumer = ResponseConsumer::new();
+        let mut send_counter: u64 = 0;
+        let mut receive_counter: u64 = 0;
+        let start = SystemTime::now();
+        let socket = match self.socket.as_ref() {
+            Some(v) => v,
+            None => return (producer.store, consumer.store),
+        };
+        loop {
+            let (packet, qtype) = match producer.retrieve() {
+                PacketGeneratorStatus::Success(data, qtype) => (data.to_vec(), qtype),
+                PacketGeneratorStatus::Wait(v) => {
+                    std::thread::sleep(Duration::from_nanos(v));
+                    continue;
+                }
+                PacketGeneratorStatus::Stop => break,
+            };
+            producer.store.update_query(qtype);
+            let mutation = MUTATIONS[send_counter as usize % MUTATIONS.len()];
+            let mut mutated = packet.clone();
+            mutated[0..2]
+                .copy_from_slice(&FuzzWorker::message_id(mutation, send_counter).to_be_bytes());
+            mutation.apply(&mut mutated);
+            send_counter += 1;
+            consumer
+                .store
+                .update_mutation(mutation, MutationResult::Sent);
+            match FuzzWorker::exchange(socket, &mutated, timeout) {
+                Some((header, duration)) => {
+                    receive_counter += 1;
+                    if header.response_code() == u16::from(ResponseCode::FormErr) as u8 {
+                        consumer
+                            .store
+                            .update_mutation(mutation, MutationResult::FormErr);
+                    } else {
+                        consumer
+                            .store
+                            .update_mutation(mutation, MutationResult::Answer);
+                    }
+                    consumer.receive(&MessageOrHeader::Header((header, duration)));
+                }
+                None => {
+                    consumer
+                        .store
+                        .update_mutation(mutation, MutationResult::NoAnswer);
+                    let mut probe = packet;
+                    probe[0..2].copy_from_slice(
+                        &FuzzWorker::message_id(Mutation::Probe, send_counter).to_be_bytes(),
+                    );
+                    if FuzzWorker::exchange(socket, &probe, timeout).is_none() {
+                        warn!("server stop answering after {} packet", mutation);
+                        consumer
+                            .store
+                            .update_mutation(mutation, MutationResult::ServerStop);
+                    }
+                }
+            }
+            if interval != 0 {
+                let now = SystemTime::now();
+                if now >= next_status_send {
+                    producer
+                        .store
+                        .set_send_duration(now.duration_since(start).unwrap());
+                    producer.update_interval_rate();
+                    consumer.store.set_receive_total(receive_counter);
+                    consumer.update_report();
+                    if let Err(err) = sender.send((producer.store.clone(), consumer.store.clone()))
+                    {
+                        error!("send interval status fail: {:?}", err)
+                    }
+                    next_status_send = now.add(Duration::from_secs(interval));
+                }
+            }
+        }
+        debug!(
+            "fuzz finished send = {} receive = {} cpu={}",
+            send_counter, receive_counter, id
+        );
+        std::mem::drop(sender);
+        producer
+            .store
+            .set_send_duration(start.elapsed().unwrap_or_default());
+        consumer.store.set_receive_total(receive_counter);
+        producer.update_total_rate();
+        consumer.receive(&MessageOrHeader::End);
+        (producer.store, consumer.store)
+    }
+}
+
+impl FuzzWorker {
+    // boxed like the other workers, the runner only holds dyn Worker
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(arguments: Argument) -> Box<dyn Worker> {
+        let server_port = format!("{}:{}", arguments.server, arguments.port);
+        let source_ip_addr = format!("{}:0", arguments.source);
+        let socket = UdpSocket::bind(source_ip_addr).expect("source ip addr is not set correct");
+        let socket = match socket.connect(server_port) {
+            Ok(_) => Some(socket),
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        };
+        Box::new(FuzzWorker { arguments, socket })
+    }
+}
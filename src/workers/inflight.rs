@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// InFlight tracks the outstanding query of each socket, at most one query
+/// is outstanding in a socket. The sockets beyond the limit are parked and
+/// woken in turn when a query is answered or expired.
+pub struct InFlight {
+    // 0 means no limit
+    limit: usize,
+    timeout: Duration,
+    sent: Vec<Option<Instant>>,
+    count: usize,
+    parked: VecDeque<usize>,
+    // the outstanding query of socket was sent in the warmup period
+    warmup: Vec<bool>,
+}
+
+impl InFlight {
+    pub fn new(sockets: usize, limit: usize, timeout: Duration) -> InFlight {
+        InFlight {
+            limit,
+            timeout,
+            sent: vec![None; sockets],
+            count: 0,
+            parked: VecDeque::new(),
+            warmup: vec![false; sockets],
+        }
+    }
+
+    /// whether the socket can send a query, the socket is parked if the
+    /// limit is reached.
+    pub fn available(&mut self, socket: usize) -> bool {
+        if self.limit == 0 || self.count < self.limit {
+            return true;
+        }
+        if !self.parked.contains(&socket) {
+            self.parked.push_back(socket);
+        }
+        false
+    }
+
+    pub fn sent(&mut self, socket: usize) {
+        if self.sent[socket].replace(Instant::now()).is_none() {
+            self.count += 1;
+        }
+    }
+
+    /// the query of socket is answered, returns the socket which should send
+    /// the next query, the parked socket goes first.
+    pub fn release(&mut self, socket: usize) -> usize {
+        if self.sent[socket].take().is_some() {
+            self.count -= 1;
+        }
+        match self.parked.pop_front() {
+            Some(parked) => {
+                self.parked.push_back(socket);
+                parked
+            }
+            None => socket,
+        }
+    }
+
+    /// release the queries waited longer than timeout, returns the sockets
+    /// of expired query with the socket which should send the next query.
+    pub fn expire(&mut self) -> Vec<(usize, usize)> {
+        let now = Instant::now();
+        let expired: Vec<usize> = (0..self.sent.len())
+            .filter(
+                |i| matches!(self.sent[*i], Some(sent) if now.duration_since(sent) >= self.timeout),
+            )
+            .collect();
+        expired.into_iter().map(|i| (i, self.release(i))).collect()
+    }
+
+    /// mark the outstanding queries when the warmup ends, their responses
+    /// are not counted.
+    pub fn end_warmup(&mut self) {
+        for (warmup, sent) in self.warmup.iter_mut().zip(self.sent.iter()) {
+            *warmup = sent.is_some();
+        }
+    }
+
+    /// whether the last query of socket was sent in the warmup period, the
+    /// mark is cleared.
+    pub fn take_warmup(&mut self, socket: usize) -> bool {
+        std::mem::replace(&mut self.warmup[socket], false)
+    }
+
+    /// the number of queries waiting for response
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::workers::inflight::InFlight;
+    use std::time::Duration;
+
+    #[test]
+    fn test_inflight() {
+        let mut inflight = InFlight::new(3, 2, Duration::from_secs(5));
+        assert!(inflight.available(0));
+        inflight.sent(0);
+        assert!(inflight.available(1));
+        inflight.sent(1);
+        assert!(!inflight.available(2));
+        assert!(!inflight.available(2));
+        assert_eq!(inflight.count(), 2);
+        // the parked socket sends next and the answered one is parked
+        assert_eq!(inflight.release(0), 2);
+        assert!(inflight.available(2));
+        inflight.sent(2);
+        assert_eq!(inflight.release(1), 0);
+        assert_eq!(inflight.release(2), 1);
+        assert_eq!(inflight.count(), 0);
+        assert!(inflight.expire().is_empty());
+
+        let mut inflight = InFlight::new(2, 0, Duration::from_millis(0));
+        inflight.sent(0);
+        inflight.sent(0);
+        assert_eq!(inflight.count(), 1);
+        assert_eq!(inflight.expire(), vec![(0, 0)]);
+        assert_eq!(inflight.count(), 0);
+
+        inflight.sent(1);
+        inflight.end_warmup();
+        assert!(!inflight.take_warmup(0));
+        assert!(inflight.take_warmup(1));
+        assert!(!inflight.take_warmup(1));
+    }
+}
@@ -10,6 +10,8 @@ pub trait Worker: Send + Sync {
 }
 
 const HEADER_SIZE: usize = 12;
+// the header and the question with the longest name
+const MAX_QUESTION_SIZE: usize = HEADER_SIZE + 255 + 4;
 
 pub enum MessageOrHeader {
     Message((Message, f64)),
@@ -17,6 +19,12 @@ pub enum MessageOrHeader {
     End,
 }
 
+pub mod allocator;
+pub mod compare;
 // pub mod dot;
+pub mod fuzz;
+pub mod inflight;
+pub mod session;
 pub mod tcp;
+pub mod transfer;
 pub mod udp;
@@ -0,0 +1,570 @@
+use super::allocator::IdAllocator;
+use crate::runner::ede::extended_errors;
+use crate::utils::dnssec::{DnssecStatus, TrustAnchor};
+use crate::utils::tsig::{TsigKey, TsigStatus};
+use crate::utils::wire::{
+    append_edns_option, first_question, pad_message, remove_edns_option, skip_name,
+    DEFAULT_PADDING_BLOCK, PADDING_CODE,
+};
+use crate::utils::{Argument, Protocol};
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use trust_dns_client::op::{Message, ResponseCode};
+use trust_dns_client::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+
+const COOKIE_CODE: u16 = 10;
+const CLIENT_COOKIE_SIZE: usize = 8;
+// server cookie is 8 to 32 bytes (RFC7873 section 4)
+const MIN_COOKIE_SIZE: usize = CLIENT_COOKIE_SIZE + 8;
+const MAX_COOKIE_SIZE: usize = CLIENT_COOKIE_SIZE + 32;
+const HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CookieStatus {
+    Valid,
+    Missing,
+    Mismatch,
+    BadCookie,
+}
+
+impl fmt::Display for CookieStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            CookieStatus::Valid => "Valid",
+            CookieStatus::Missing => "Missing",
+            CookieStatus::Mismatch => "Mismatch",
+            CookieStatus::BadCookie => "BadCookie",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// CaseStatus is the result of checking whether the response echoes the
+/// exact case of the randomized query name (dns-0x20).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaseStatus {
+    Match,
+    Mismatch,
+    // the response has no question section
+    Missing,
+}
+
+impl fmt::Display for CaseStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            CaseStatus::Match => "Match",
+            CaseStatus::Mismatch => "Mismatch",
+            CaseStatus::Missing => "Missing",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// Inspection is the result of checking a response against the state
+/// of the session.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Inspection {
+    pub cookie: Option<CookieStatus>,
+    pub tsig: Option<TsigStatus>,
+    pub case: Option<CaseStatus>,
+    pub dnssec: Option<DnssecStatus>,
+    // the error of TSIG record, for example BADSIG, BADKEY and BADTIME
+    pub tsig_error: u16,
+    pub response_code: u16,
+    // the size of response message without tcp length
+    pub size: usize,
+    // the info code and extra text of extended dns errors (RFC 8914)
+    pub extended_error: Vec<(u16, String)>,
+}
+
+impl Inspection {
+    /// the TSIG error reported by server or found by verifying the response,
+    /// it is counted as the response code in report.
+    pub fn tsig_response_code(&self) -> Option<u16> {
+        if self.tsig_error != 0 {
+            return Some(self.tsig_error);
+        }
+        match self.tsig? {
+            TsigStatus::BadSig => Some(u16::from(ResponseCode::BADSIG)),
+            TsigStatus::BadTime => Some(u16::from(ResponseCode::BADTIME)),
+            _ => None,
+        }
+    }
+}
+
+struct Cookie {
+    client: [u8; CLIENT_COOKIE_SIZE],
+    server: Option<Vec<u8>>,
+}
+
+/// Session keeps the state of one socket which can't be prebuilt in
+/// the cache, for example the server cookie learned from the responses
+/// and the message id and TSIG signature of the outstanding queries.
+pub struct Session {
+    offset: usize,
+    // None if the message id is fixed by argument
+    ids: Option<IdAllocator>,
+    padding: usize,
+    cookie: Option<Cookie>,
+    tsig: Option<TsigKey>,
+    // query id to the mac of signed query
+    signatures: HashMap<u16, Vec<u8>>,
+    // query id to the query name with randomized case, None if disabled
+    names: Option<HashMap<u16, Vec<u8>>>,
+    anchor: Option<TrustAnchor>,
+    // the prepared packet, reused by the queries of this socket
+    scratch: Vec<u8>,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or(0)
+}
+
+impl Session {
+    pub fn new(arguments: &Argument) -> Session {
+        Session {
+            offset: match arguments.protocol {
+                Protocol::TCP | Protocol::DOT => 2,
+                Protocol::UDP | Protocol::DOH => 0,
+            },
+            ids: {
+                if arguments.packet_id == 0 && !arguments.keep_id {
+                    Some(IdAllocator::new(
+                        arguments.random_id,
+                        Duration::from_secs(arguments.timeout as u64),
+                    ))
+                } else {
+                    None
+                }
+            },
+            padding: {
+                if arguments.edns_padding > 0 {
+                    arguments.edns_padding
+                } else {
+                    DEFAULT_PADDING_BLOCK
+                }
+            },
+            cookie: {
+                if arguments.enable_cookie {
+                    Some(Cookie {
+                        client: rand::thread_rng().gen(),
+                        server: None,
+                    })
+                } else {
+                    None
+                }
+            },
+            tsig: arguments.tsig.clone(),
+            signatures: HashMap::new(),
+            names: {
+                if arguments.enable_0x20 {
+                    Some(HashMap::new())
+                } else {
+                    None
+                }
+            },
+            anchor: arguments.trust_anchor.clone(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// prepare the packet from cache before sending it through the socket,
+    /// the packet is borrowed as it is when nothing need to change, or it
+    /// is rewritten in the scratch buffer of session.
+    pub fn prepare<'a>(&'a mut self, data: &'a [u8]) -> &'a [u8] {
+        if self.ids.is_none()
+            && self.cookie.is_none()
+            && self.tsig.is_none()
+            && self.names.is_none()
+        {
+            return data;
+        }
+        let mut message = std::mem::take(&mut self.scratch);
+        message.clear();
+        message.extend_from_slice(&data[self.offset..]);
+        if let Some(ids) = self.ids.as_mut() {
+            let id = ids.allocate(first_question(&message).unwrap_or_default());
+            message[0..2].copy_from_slice(&id.to_be_bytes());
+        }
+        if let Some(names) = self.names.as_mut() {
+            if let Some(end) = skip_name(&message, HEADER_SIZE) {
+                let mut rng = rand::thread_rng();
+                for byte in message[HEADER_SIZE..end].iter_mut() {
+                    if byte.is_ascii_alphabetic() && rng.gen() {
+                        *byte ^= 0x20;
+                    }
+                }
+                names.insert(
+                    u16::from_be_bytes([message[0], message[1]]),
+                    message[HEADER_SIZE..end].to_vec(),
+                );
+            }
+        }
+        // the padding must be recalculated after the cookie and TSIG appended
+        let padded = (self.cookie.is_some() || self.tsig.is_some())
+            && remove_edns_option(&mut message, PADDING_CODE);
+        if let Some(cookie) = self.cookie.as_ref() {
+            let mut option = cookie.client.to_vec();
+            if let Some(server) = cookie.server.as_ref() {
+                option.extend_from_slice(server);
+            }
+            if !append_edns_option(&mut message, COOKIE_CODE, &option) {
+                warn!("no opt record found for dns cookie");
+            }
+        }
+        if padded {
+            let trailing = self.tsig.as_ref().map_or(0, |key| key.record_size());
+            pad_message(&mut message, self.padding, trailing);
+        }
+        // the TSIG record must be the last one and sign the whole message
+        if let Some(key) = self.tsig.as_ref() {
+            let mac = key.sign(&mut message, unix_time());
+            self.signatures
+                .insert(u16::from_be_bytes([message[0], message[1]]), mac);
+        }
+        if self.offset != 0 {
+            let size = (message.len() as u16).to_be_bytes();
+            message.splice(0..0, size.iter().copied());
+        }
+        self.scratch = message;
+        &self.scratch
+    }
+
+    /// check whether the response answers an outstanding query of this
+    /// socket by id and question, the unexpected response must not be
+    /// counted as answer.
+    pub fn accept(&mut self, raw: &[u8]) -> bool {
+        match self.ids.as_mut() {
+            Some(ids) if raw.len() >= HEADER_SIZE => {
+                ids.release(u16::from_be_bytes([raw[0], raw[1]]), first_question(raw))
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    /// the outstanding query of this socket is given up after timeout, the
+    /// state kept for checking its response is dropped as well.
+    pub fn expire(&mut self) {
+        match self.ids.as_mut() {
+            Some(ids) => {
+                ids.expire();
+                self.signatures.retain(|id, _| ids.is_outstanding(*id));
+                if let Some(names) = self.names.as_mut() {
+                    names.retain(|id, _| ids.is_outstanding(*id));
+                }
+            }
+            // the fixed id can't tell the queries apart
+            None => {
+                self.signatures.clear();
+                if let Some(names) = self.names.as_mut() {
+                    names.clear();
+                }
+            }
+        }
+    }
+
+    /// check the response with the state of session, the raw message is
+    /// needed to verify the TSIG signature.
+    pub fn inspect(&mut self, raw: &[u8], message: &Message) -> Inspection {
+        let mut inspection = Inspection {
+            cookie: self.inspect_cookie(message),
+            response_code: u16::from(message.response_code()),
+            size: raw.len(),
+            extended_error: extended_errors(raw),
+            dnssec: self
+                .anchor
+                .as_ref()
+                .map(|anchor| anchor.validate(message, unix_time() as u32)),
+            ..Default::default()
+        };
+        if let Some(key) = self.tsig.as_ref() {
+            let mac = self.signatures.remove(&message.id()).unwrap_or_default();
+            let (status, error) = key.verify(raw, &mac, unix_time());
+            inspection.tsig = Some(status);
+            inspection.tsig_error = error;
+        }
+        if let Some(names) = self.names.as_mut() {
+            if let Some(name) = names.remove(&message.id()) {
+                inspection.case = Some(match raw.get(HEADER_SIZE..HEADER_SIZE + name.len()) {
+                    _ if message.query_count() == 0 => CaseStatus::Missing,
+                    Some(echoed) if echoed == name.as_slice() => CaseStatus::Match,
+                    _ => CaseStatus::Mismatch,
+                });
+            }
+        }
+        inspection
+    }
+
+    /// check the cookie in response and learn the server cookie which
+    /// will be echoed in the following queries of this socket.
+    fn inspect_cookie(&mut self, message: &Message) -> Option<CookieStatus> {
+        let cookie = self.cookie.as_mut()?;
+        let bad_cookie = message.response_code() == ResponseCode::BADCOOKIE;
+        let data = match message.edns().and_then(|e| e.option(EdnsCode::Cookie)) {
+            Some(EdnsOption::Unknown(_, data)) => data,
+            _ => {
+                return Some(if bad_cookie {
+                    CookieStatus::BadCookie
+                } else {
+                    CookieStatus::Missing
+                })
+            }
+        };
+        if data.len() < MIN_COOKIE_SIZE
+            || data.len() > MAX_COOKIE_SIZE
+            || data[..CLIENT_COOKIE_SIZE] != cookie.client
+        {
+            return Some(if bad_cookie {
+                CookieStatus::BadCookie
+            } else {
+                CookieStatus::Mismatch
+            });
+        }
+        cookie.server = Some(data[CLIENT_COOKIE_SIZE..].to_vec());
+        Some(if bad_cookie {
+            CookieStatus::BadCookie
+        } else {
+            CookieStatus::Valid
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runner::cache::Cache;
+    use crate::utils::tsig::TsigStatus;
+    use crate::utils::{Argument, Protocol};
+    use crate::workers::session::{CaseStatus, CookieStatus, Session};
+    use trust_dns_client::op::{Edns, Message, ResponseCode};
+    use trust_dns_client::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+    use trust_dns_client::rr::RecordType;
+
+    fn response_with_cookie(cookie: Option<Vec<u8>>, code: ResponseCode) -> Message {
+        let mut message = Message::new();
+        let mut edns = Edns::new();
+        edns.set_rcode_high(code.high());
+        if let Some(cookie) = cookie {
+            edns.options_mut()
+                .insert(EdnsOption::Unknown(u16::from(EdnsCode::Cookie), cookie));
+        }
+        message.set_edns(edns);
+        message.set_response_code(code);
+        message
+    }
+
+    #[test]
+    fn test_cookie() {
+        let arg = Argument {
+            enable_cookie: true,
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+
+        let query = Message::from_vec(session.prepare(&packet)).unwrap();
+        let client = match query.edns().unwrap().option(EdnsCode::Cookie) {
+            Some(EdnsOption::Unknown(_, v)) => v.clone(),
+            _ => panic!("no client cookie in query"),
+        };
+        assert_eq!(client.len(), 8);
+
+        let response = response_with_cookie(None, ResponseCode::NoError);
+        assert_eq!(
+            session.inspect_cookie(&response),
+            Some(CookieStatus::Missing)
+        );
+        let response = response_with_cookie(Some(vec![0; 16]), ResponseCode::NoError);
+        assert_eq!(
+            session.inspect_cookie(&response),
+            Some(CookieStatus::Mismatch)
+        );
+
+        let mut server_cookie = client.clone();
+        server_cookie.extend_from_slice(&[7; 8]);
+        let response = response_with_cookie(Some(server_cookie.clone()), ResponseCode::BADCOOKIE);
+        assert_eq!(
+            session.inspect_cookie(&response),
+            Some(CookieStatus::BadCookie)
+        );
+        let response = response_with_cookie(Some(server_cookie.clone()), ResponseCode::NoError);
+        let inspection = session.inspect(&response.to_vec().unwrap(), &response);
+        assert_eq!(inspection.cookie, Some(CookieStatus::Valid));
+        assert_eq!(inspection.tsig, None);
+
+        let query = Message::from_vec(session.prepare(&packet)).unwrap();
+        match query.edns().unwrap().option(EdnsCode::Cookie) {
+            Some(EdnsOption::Unknown(_, v)) => assert_eq!(v, &server_cookie),
+            _ => panic!("no server cookie echoed in query"),
+        }
+    }
+
+    #[test]
+    fn test_cookie_with_padding() {
+        let arg = Argument {
+            enable_cookie: true,
+            edns_padding: 64,
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+        assert_eq!(packet.len() % 64, 0);
+        let prepared = session.prepare(&packet);
+        assert_eq!(prepared.len() % 64, 0);
+        let query = Message::from_vec(prepared).unwrap();
+        assert!(query.edns().unwrap().option(EdnsCode::Cookie).is_some());
+        assert!(query.edns().unwrap().option(EdnsCode::Padding).is_some());
+    }
+
+    #[test]
+    fn test_tsig() {
+        let arg = Argument {
+            enable_cookie: true,
+            tsig: Some("test-key:hmac-sha256:c2VjcmV0".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+        let prepared = session.prepare(&packet).to_vec();
+        let query = Message::from_vec(&prepared).unwrap();
+        assert!(query.edns().unwrap().option(EdnsCode::Cookie).is_some());
+        let tsig = query.additionals().last().unwrap();
+        assert_eq!(tsig.record_type(), RecordType::Unknown(250));
+        assert_eq!(session.signatures.len(), 1);
+
+        // the query echoed back is not signed as response
+        let inspection = session.inspect(&prepared, &query);
+        assert_eq!(inspection.tsig, Some(TsigStatus::BadSig));
+        assert_eq!(inspection.tsig_response_code(), Some(16));
+        assert!(session.signatures.is_empty());
+        let response = response_with_cookie(None, ResponseCode::NoError);
+        let inspection = session.inspect(&response.to_vec().unwrap(), &response);
+        assert_eq!(inspection.tsig, Some(TsigStatus::Unsigned));
+        assert_eq!(inspection.tsig_response_code(), None);
+    }
+
+    #[test]
+    fn test_tsig_with_padding() {
+        let arg = Argument {
+            edns_padding: 128,
+            tsig: Some("test-key:hmac-sha512:c2VjcmV0".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+        let prepared = session.prepare(&packet).to_vec();
+        // the signed query is padded to the block with the TSIG record
+        assert_eq!(prepared.len(), 256);
+        let query = Message::from_vec(&prepared).unwrap();
+        assert!(query.edns().unwrap().option(EdnsCode::Padding).is_some());
+        let tsig = query.additionals().last().unwrap();
+        assert_eq!(tsig.record_type(), RecordType::Unknown(250));
+    }
+
+    #[test]
+    fn test_case_randomization() {
+        let arg = Argument {
+            enable_0x20: true,
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        let domain = "abcdefghijklmnopqrstuvwxyz.example.com";
+        let packet = Cache::build_packet(domain.to_string(), RecordType::A, &arg).unwrap();
+        let prepared = session.prepare(&packet).to_vec();
+        assert_eq!(prepared.len(), packet.len());
+        let query = Message::from_vec(&prepared).unwrap();
+        let name = query.queries()[0].name().to_string();
+        assert_ne!(name, format!("{}.", domain));
+        assert_eq!(name.to_lowercase(), format!("{}.", domain));
+
+        // the query echoed back keeps the case
+        let inspection = session.inspect(&prepared, &query);
+        assert_eq!(inspection.case, Some(CaseStatus::Match));
+        assert!(session.names.as_ref().unwrap().is_empty());
+
+        let prepared = session.prepare(&packet).to_vec();
+        let mut lowercase = prepared.clone();
+        lowercase[12..12 + domain.len() + 2].make_ascii_lowercase();
+        let response = Message::from_vec(&lowercase).unwrap();
+        let inspection = session.inspect(&lowercase, &response);
+        assert_eq!(inspection.case, Some(CaseStatus::Mismatch));
+    }
+
+    #[test]
+    fn test_accept() {
+        let arg = Argument::default();
+        let mut session = Session::new(&arg);
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+        let first = session.prepare(&packet).to_vec();
+        let second = session.prepare(&packet).to_vec();
+        assert_ne!(first[..2], second[..2]);
+
+        let other = Cache::build_packet("example.org".to_string(), RecordType::A, &arg).unwrap();
+        let mut unexpected = other.clone();
+        unexpected[..2].copy_from_slice(&first[..2]);
+        assert!(!session.accept(&unexpected));
+        assert!(session.accept(&first));
+        assert!(!session.accept(&first));
+        assert!(session.accept(&second));
+
+        let arg = Argument {
+            packet_id: 7,
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        assert_eq!(session.prepare(&packet)[..2], packet[..2]);
+        assert!(session.accept(&other));
+    }
+
+    #[test]
+    fn test_tcp_length() {
+        let arg = Argument {
+            protocol: Protocol::TCP,
+            tsig: Some("test-key:hmac-sha256:c2VjcmV0".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+        for _ in 0..2 {
+            let prepared = session.prepare(&packet).to_vec();
+            let size = u16::from_be_bytes([prepared[0], prepared[1]]) as usize;
+            assert_eq!(size, prepared.len() - 2);
+            assert!(Message::from_vec(&prepared[2..]).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_expire() {
+        let arg = Argument {
+            enable_0x20: true,
+            tsig: Some("test-key:hmac-sha256:c2VjcmV0".parse().unwrap()),
+            timeout: 0,
+            ..Default::default()
+        };
+        let mut session = Session::new(&arg);
+        let packet = Cache::build_packet("example.com".to_string(), RecordType::A, &arg).unwrap();
+        session.prepare(&packet);
+        session.prepare(&packet);
+        assert_eq!(session.signatures.len(), 2);
+        assert_eq!(session.names.as_ref().unwrap().len(), 2);
+        // the state of unanswered queries is dropped
+        session.expire();
+        assert!(session.signatures.is_empty());
+        assert!(session.names.as_ref().unwrap().is_empty());
+
+        let arg = Argument {
+            packet_id: 7,
+            ..arg
+        };
+        let mut session = Session::new(&arg);
+        session.prepare(&packet);
+        assert_eq!(session.signatures.len(), 1);
+        session.expire();
+        assert!(session.signatures.is_empty());
+    }
+}
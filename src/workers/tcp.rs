@@ -1,11 +1,14 @@
 use std::io::{Read, Write};
-use trust_dns_client::op::Header;
+use trust_dns_client::op::{Header, Message, ResponseCode};
 use trust_dns_client::proto::serialize::binary::BinDecodable;
 
+use super::inflight::InFlight;
+use super::session::Session;
 use super::{MessageOrHeader, Worker, HEADER_SIZE};
 use crate::runner::consumer::ResponseConsumer;
 use crate::runner::producer::PacketGeneratorStatus;
 use crate::runner::report::StatusStore;
+use crate::runner::safeguard::Safeguard;
 use crate::runner::QueryProducer;
 use crate::utils::Argument;
 use mio::event::Event;
@@ -18,6 +21,9 @@ pub struct TCPWorker {
     poll: Poll,
     events: Events,
     sockets: Vec<TcpStream>,
+    sessions: Vec<Session>,
+    // the data received but not a complete message yet of each socket
+    buffers: Vec<Vec<u8>>,
     // server_port: String,
 }
 
@@ -43,7 +49,20 @@ impl TCPWorker {
         }
     }
 
-    fn read_data(connection: &mut TcpStream, data: &mut [u8]) -> SocketStatus {
+    /// take the first complete message with its 2 bytes length from the
+    /// data received, None if the message is not complete yet.
+    fn take_frame(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+        if buffer.len() < 2 {
+            return None;
+        }
+        let size = ((buffer[0] as usize) << 8) | buffer[1] as usize;
+        if buffer.len() < size + 2 {
+            return None;
+        }
+        Some(buffer.drain(..size + 2).collect())
+    }
+
+    fn read_data(connection: &mut TcpStream, data: &mut Vec<u8>) -> SocketStatus {
         let mut received_data = vec![0; 512];
         let mut bytes_read = 0;
         loop {
@@ -74,7 +93,8 @@ impl TCPWorker {
             };
         }
         if bytes_read != 0 {
-            data.copy_from_slice(&received_data[0..data.len()]);
+            received_data.truncate(bytes_read);
+            *data = received_data;
             SocketStatus::Success
         } else {
             debug!("read zero byte");
@@ -95,25 +115,42 @@ impl Worker for TCPWorker {
             std::time::SystemTime::now().add(std::time::Duration::from_secs(interval));
         let mut producer = QueryProducer::new(arguments.clone());
         let mut consumer = ResponseConsumer::new();
-        #[allow(unused_assignments)]
-        let mut stop_sender_timer = std::time::SystemTime::now();
+        let mut safeguard = Safeguard::new(&arguments);
+        consumer.set_expectations(producer.expectations().clone());
         let max_send = arguments.max as u64;
+        let mut parse_full_response = producer.parse_full_response();
         let mut send_counter: u64 = 0;
         let mut receive_counter: u64 = 0;
-        let start = std::time::SystemTime::now();
+        let mut start = std::time::SystemTime::now();
+        let mut stop_sender_timer = start;
+        // the last time a query is sent or the pacer holds the next one, a
+        // worker with low rate share waits longer than the idle limit
+        let mut last_active = start;
+        let mut inflight = InFlight::new(
+            self.sockets.len(),
+            arguments.max_inflight,
+            std::time::Duration::from_secs(arguments.timeout as u64),
+        );
         if let Err(e) = self.poll.poll(&mut self.events, None) {
             error!("poll event fail: {}", e.to_string());
         };
         let mut time_store = HashMap::new();
-        let mut dns_packet = vec![0u8; 14];
+        let mut dns_packet = vec![];
         'outer: loop {
             for event in self.events.iter() {
                 let token = event.token();
                 let ref mut connection = self.sockets[token.0];
-                if event.is_writable() {
+                if event.is_writable() && !inflight.available(token.0) {
+                    // parked until another query is answered
+                    self.poll
+                        .registry()
+                        .reregister(connection, token, Interest::READABLE)
+                        .expect("reregister fail");
+                } else if event.is_writable() {
                     // debug!("socket {} is writable", token.0);
                     match producer.retrieve() {
                         PacketGeneratorStatus::Success(data, qtype) => {
+                            let data = self.sessions[token.0].prepare(data);
                             let key = ((data[2] as u16) << 8) | (data[3] as u16);
                             // sample 1/10
                             if key % 10 == 1 {
@@ -124,6 +161,8 @@ impl Worker for TCPWorker {
                                     send_counter += 1;
                                     producer.store.update_query(qtype);
                                     stop_sender_timer = std::time::SystemTime::now();
+                                    last_active = stop_sender_timer;
+                                    inflight.sent(token.0);
                                     //
                                     debug!(
                                         "send success receive = {},  current = {}",
@@ -150,6 +189,7 @@ impl Worker for TCPWorker {
                         }
                         PacketGeneratorStatus::Wait(_) => {
                             // debug!("wait for next ticker");
+                            last_active = std::time::SystemTime::now();
                             self.poll
                                 .registry()
                                 .reregister(connection, token, Interest::WRITABLE)
@@ -161,43 +201,85 @@ impl Worker for TCPWorker {
                     }
                 }
                 if event.is_readable() {
-                    let result = TCPWorker::read_data(connection, dns_packet.as_mut_slice());
+                    let result = TCPWorker::read_data(connection, &mut dns_packet);
                     match result {
                         SocketStatus::Success => {
+                            // the response may arrive in several reads
+                            self.buffers[token.0].extend_from_slice(&dns_packet);
+                        }
+                        SocketStatus::Close => {
+                            debug!("reset socket");
+                            continue;
+                        }
+                        _ => {
+                            error!("read socket fail");
+                            continue;
+                        }
+                    }
+                    while let Some(frame) = TCPWorker::take_frame(&mut self.buffers[token.0]) {
+                        let message = &frame[2..];
+                        if message.len() < HEADER_SIZE {
+                            error!("receive incomplete dns message");
+                            continue;
+                        }
+                        if !self.sessions[token.0].accept(message) {
+                            debug!("unexpected response in socket {} cpu={}", token.0, id);
+                            consumer.store.update_unexpected();
                             self.poll
                                 .registry()
-                                .reregister(connection, token, Interest::WRITABLE)
+                                .reregister(&mut self.sockets[token.0], token, Interest::READABLE)
                                 .expect("reregister fail");
-                            let key = ((dns_packet[2] as u16) << 8) | (dns_packet[3] as u16);
-                            // sample 1/10
-                            let mut duration: f64 = 0.0;
-                            if key % 10 == 1 {
-                                if let Some(start) = time_store.get(&key) {
-                                    duration = start.elapsed().unwrap().as_secs_f64();
-                                }
-                            }
-                            receive_counter += 1;
-                            debug!(
-                                "receive success receive = {},  send = {}",
-                                receive_counter, send_counter
-                            );
-                            if let Ok(message) = Header::from_bytes(&dns_packet[2..HEADER_SIZE + 2])
-                            {
-                                consumer.receive(&MessageOrHeader::Header((message, duration)));
-                            } else {
-                                error!("parse dns message error");
-                                continue;
+                            continue;
+                        }
+                        let next = inflight.release(token.0);
+                        if next != token.0 {
+                            self.poll
+                                .registry()
+                                .reregister(&mut self.sockets[token.0], token, Interest::READABLE)
+                                .expect("reregister fail");
+                        }
+                        self.poll
+                            .registry()
+                            .reregister(&mut self.sockets[next], Token(next), Interest::WRITABLE)
+                            .expect("reregister fail");
+                        // the answer of query sent in warmup is not counted
+                        if inflight.take_warmup(token.0) {
+                            continue;
+                        }
+                        let key = ((message[0] as u16) << 8) | (message[1] as u16);
+                        // sample 1/10
+                        let mut duration: f64 = 0.0;
+                        if key % 10 == 1 {
+                            if let Some(start) = time_store.get(&key) {
+                                duration = start.elapsed().unwrap().as_secs_f64();
                             }
                         }
-                        SocketStatus::Close => {
-                            debug!("reset socket");
+                        receive_counter += 1;
+                        debug!(
+                            "receive success receive = {},  send = {}",
+                            receive_counter, send_counter
+                        );
+                        if parse_full_response {
+                            match Message::from_vec(message) {
+                                Ok(parsed) => {
+                                    let inspection =
+                                        self.sessions[token.0].inspect(message, &parsed);
+                                    consumer.receive(&MessageOrHeader::Message((parsed, duration)));
+                                    consumer.store.update_inspection(&inspection);
+                                }
+                                Err(e) => error!("parse dns message error: {}", e),
+                            }
+                        } else if let Ok(header) = Header::from_bytes(&message[..HEADER_SIZE]) {
+                            consumer.receive(&MessageOrHeader::Header((header, duration)));
+                        } else {
+                            error!("parse dns message error");
                         }
-                        _ => error!("read socket fail"),
                     }
                 }
             }
-            if (max_send > 0 && (receive_counter == max_send))
-                || stop_sender_timer.elapsed().unwrap() > std::time::Duration::from_secs(5)
+            if (max_send > 0 && (receive_counter == max_send) && !producer.warming_up())
+                || last_active.elapsed().unwrap_or_default() > std::time::Duration::from_secs(5)
+                || (producer.stop_requested() && inflight.count() == 0)
             {
                 debug!(
                     "should break loop send = {} receive = {} cpu={}",
@@ -207,17 +289,58 @@ impl Worker for TCPWorker {
             }
             if let Err(e) = self.poll.poll(
                 &mut self.events,
-                Option::from(std::time::Duration::from_secs(1)),
+                Option::from(std::time::Duration::from_millis(100)),
             ) {
                 error!("poll event fail: {}", e.to_string());
                 break;
             }
+            // the status of warmup is dropped and the measurement starts
+            if producer.end_warmup() {
+                consumer.reset();
+                inflight.end_warmup();
+                safeguard.reset();
+                send_counter = 0;
+                receive_counter = 0;
+                start = std::time::SystemTime::now();
+            }
+            if !producer.stopped() {
+                if let Some(reason) = safeguard.check(
+                    std::time::Instant::now(),
+                    send_counter,
+                    receive_counter,
+                    consumer
+                        .store
+                        .reply_code_total(u16::from(ResponseCode::ServFail)),
+                ) {
+                    producer.abort(reason);
+                }
+            }
+            // the run is not over while it is paused by control command
+            if producer.paused() {
+                last_active = std::time::SystemTime::now();
+            }
+            if producer.take_source_change() {
+                consumer.set_expectations(producer.expectations().clone());
+                parse_full_response = producer.parse_full_response();
+            }
+            // give up the queries without response in timeout
+            for (expired, next) in inflight.expire() {
+                self.sessions[expired].expire();
+                if !inflight.take_warmup(expired) {
+                    consumer.store.update_timeout();
+                }
+                self.poll
+                    .registry()
+                    .reregister(&mut self.sockets[next], Token(next), Interest::WRITABLE)
+                    .expect("reregister fail");
+            }
             if interval != 0 {
                 let now = std::time::SystemTime::now();
                 if now >= next_status_send {
                     producer
                         .store
                         .set_send_duration(now.duration_since(start.clone()).unwrap());
+                    producer.update_interval_rate();
                     consumer.store.set_receive_total(receive_counter);
                     consumer.update_report();
                     if let Err(err) = sender.send((producer.store.clone(), consumer.store.clone()))
@@ -231,8 +354,9 @@ impl Worker for TCPWorker {
         std::mem::drop(sender);
         producer
             .store
-            .set_send_duration(stop_sender_timer.duration_since(start).unwrap());
+            .set_send_duration(stop_sender_timer.duration_since(start).unwrap_or_default());
         consumer.store.set_receive_total(receive_counter);
+        producer.update_total_rate();
         consumer.receive(&MessageOrHeader::End);
         for socket in self.sockets.iter_mut() {
             self.poll
@@ -250,6 +374,7 @@ impl TCPWorker {
         let poll = Poll::new().expect("create async poll fail");
         let events = Events::with_capacity(1024);
         let mut sockets = vec![];
+        let mut sessions = vec![];
 
         for i in 0..arguments.client {
             match TcpStream::connect(
@@ -267,6 +392,7 @@ impl TCPWorker {
                         .expect("registr event fail");
                     debug!("register Interest::WRITABLE for socket {}", i);
                     sockets.push(stream);
+                    sessions.push(Session::new(&arguments));
                 }
             }
         }
@@ -275,6 +401,8 @@ impl TCPWorker {
             poll,
             events,
             sockets,
+            buffers: vec![vec![]; sessions.len()],
+            sessions,
             // server_port,
         })
     }
@@ -287,3 +415,23 @@ fn would_block(err: &std::io::Error) -> bool {
 fn interrupted(err: &std::io::Error) -> bool {
     err.kind() == std::io::ErrorKind::Interrupted
 }
+
+#[cfg(test)]
+mod test {
+    use crate::workers::tcp::TCPWorker;
+
+    #[test]
+    fn test_take_frame() {
+        let mut buffer = vec![0, 3, 1, 2];
+        assert_eq!(TCPWorker::take_frame(&mut buffer), None);
+        // the rest of first message and the second message in one read
+        buffer.extend_from_slice(&[3, 0, 1, 4, 0]);
+        assert_eq!(
+            TCPWorker::take_frame(&mut buffer),
+            Some(vec![0, 3, 1, 2, 3])
+        );
+        assert_eq!(TCPWorker::take_frame(&mut buffer), Some(vec![0, 1, 4]));
+        assert_eq!(TCPWorker::take_frame(&mut buffer), None);
+        assert_eq!(buffer, vec![0]);
+    }
+}
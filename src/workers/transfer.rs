@@ -0,0 +1,522 @@
+use std::io::{Read, Write};
+use trust_dns_client::op::{Message, ResponseCode};
+use trust_dns_client::rr::RData;
+
+use super::session::Session;
+use super::{MessageOrHeader, Worker, HEADER_SIZE};
+use crate::runner::consumer::ResponseConsumer;
+use crate::runner::producer::PacketGeneratorStatus;
+use crate::runner::report::StatusStore;
+use crate::runner::QueryProducer;
+use crate::utils::{Argument, Mode};
+use mio::{net::TcpStream, Events, Interest, Poll, Token};
+use std::ops::Add;
+use std::time::{Duration, SystemTime};
+
+/// Progress is the state of the transfer after a message received
+#[derive(Debug, PartialEq)]
+enum Progress {
+    Continue,
+    Finished,
+    // the transfer is refused or broken, with the response code or reason
+    Failed(String),
+}
+
+/// Transfer keeps the state of the zone transfer in progress on a connection,
+/// the transfer may be sent in multiple messages.
+#[derive(Default)]
+struct Transfer {
+    buffer: Vec<u8>,
+    start: Option<SystemTime>,
+    // the time of query sent or the last data received
+    active: Option<SystemTime>,
+    resume_at: Option<SystemTime>,
+    serial: Option<u32>,
+    serial_count: usize,
+    incremental: Option<bool>,
+    records: u64,
+    bytes: u64,
+    messages: u64,
+}
+
+impl Transfer {
+    fn reset(&mut self) {
+        *self = Transfer {
+            buffer: std::mem::take(&mut self.buffer),
+            ..Default::default()
+        };
+    }
+
+    /// read the records of message, return the progress of the transfer.
+    /// the AXFR is finished by the second SOA record, and the incremental
+    /// IXFR is finished by the third SOA record with the serial of server.
+    fn receive(&mut self, message: &Message, size: usize, mode: &Mode) -> Progress {
+        self.messages += 1;
+        self.bytes += size as u64;
+        if message.response_code() != ResponseCode::NoError {
+            return Progress::Failed(format!("{:?}", message.response_code()));
+        }
+        for record in message.answers() {
+            let index = self.records;
+            self.records += 1;
+            let serial = match record.rdata() {
+                RData::SOA(soa) => Some(soa.serial()),
+                _ => None,
+            };
+            match (index, serial, self.serial) {
+                (0, Some(serial), _) => {
+                    self.serial = Some(serial);
+                    self.serial_count = 1;
+                }
+                // the first record of transfer must be SOA
+                (0, None, _) => return Progress::Failed("Malformed".to_string()),
+                (1, Some(serial), Some(first)) => {
+                    if *mode == Mode::Axfr || serial == first {
+                        return Progress::Finished;
+                    }
+                    self.incremental = Some(true);
+                }
+                (1, None, _) => self.incremental = Some(false),
+                (_, Some(serial), Some(first)) => {
+                    if self.incremental != Some(true) {
+                        return Progress::Finished;
+                    }
+                    if serial == first {
+                        self.serial_count += 1;
+                        if self.serial_count == 3 {
+                            return Progress::Finished;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        match self.records {
+            0 => Progress::Failed("Malformed".to_string()),
+            // the IXFR response with only one SOA means the zone is up to date
+            1 if *mode == Mode::Ixfr => Progress::Finished,
+            _ => Progress::Continue,
+        }
+    }
+}
+
+pub struct TransferWorker {
+    arguments: Argument,
+    poll: Poll,
+    events: Events,
+    sockets: Vec<TcpStream>,
+    sessions: Vec<Session>,
+    transfers: Vec<Transfer>,
+}
+
+impl TransferWorker {
+    // read all the data from connection, return false if the connection closed
+    fn read_data(connection: &mut TcpStream, data: &mut Vec<u8>) -> bool {
+        let mut received_data = vec![0; 4096];
+        loop {
+            match connection.read(&mut received_data) {
+                Ok(0) => return false,
+                Ok(n) => data.extend_from_slice(&received_data[..n]),
+                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => return true,
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    debug!("read data error: {}", err);
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl Worker for TransferWorker {
+    fn run(
+        &mut self,
+        id: usize,
+        sender: crossbeam_channel::Sender<(StatusStore, StatusStore)>,
+    ) -> (StatusStore, StatusStore) {
+        let arguments = self.arguments.clone();
+        let interval = arguments.output_interval as u64;
+        let transfer_interval = Duration::from_secs(arguments.transfer_interval as u64);
+        let timeout = Duration::from_secs(arguments.timeout as u64);
+        let mut next_status_send = SystemTime::now().add(Duration::from_secs(interval));
+        let mut producer = QueryProducer::new(arguments.clone());
+        let mut consumer = ResponseConsumer::new();
+        let max_send = arguments.max as u64;
+        let mut send_counter: u64 = 0;
+        let mut receive_counter: u64 = 0;
+        let mut stopped = false;
+        let start = SystemTime::now();
+        let mut last_active = SystemTime::now();
+        let mut closed = vec![false; self.sockets.len()];
+        if let Err(e) = self.poll.poll(&mut self.events, None) {
+            error!("poll event fail: {}", e);
+        };
+        'outer: loop {
+            for event in self.events.iter() {
+                let token = event.token();
+                let i = token.0;
+                let connection = &mut self.sockets[i];
+                let transfer = &mut self.transfers[i];
+                if event.is_writable() && transfer.start.is_none() && transfer.resume_at.is_none() {
+                    match producer.retrieve() {
+                        PacketGeneratorStatus::Success(data, qtype) => {
+                            let data = self.sessions[i].prepare(data);
+                            match connection.write(data) {
+                                Ok(n) if n == data.len() => {
+                                    send_counter += 1;
+                                    producer.store.update_query(qtype);
+                                    transfer.start = Some(SystemTime::now());
+                                    transfer.active = transfer.start;
+                                    last_active = SystemTime::now();
+                                    self.poll
+                                        .registry()
+                                        .reregister(connection, token, Interest::READABLE)
+                                        .expect("reregister fail");
+                                }
+                                Ok(_) => {
+                                    error!("partial write fail");
+                                    producer.return_back();
+                                }
+                                Err(e) => {
+                                    debug!("send error: {}", e);
+                                    producer.return_back();
+                                }
+                            }
+                        }
+                        PacketGeneratorStatus::Wait(_) => {
+                            self.poll
+                                .registry()
+                                .reregister(connection, token, Interest::WRITABLE)
+                                .expect("reregister fail");
+                        }
+                        PacketGeneratorStatus::Stop => {
+                            debug!("receive stop signal");
+                            stopped = true;
+                        }
+                    }
+                }
+                if event.is_readable() {
+                    if !TransferWorker::read_data(connection, &mut transfer.buffer) {
+                        debug!("connection {} closed by server", i);
+                        closed[i] = true;
+                        if transfer.start.is_some() {
+                            error!("connection closed before the transfer finished");
+                            consumer.store.transfer.fail("Closed");
+                        }
+                        transfer.reset();
+                        self.poll
+                            .registry()
+                            .deregister(connection)
+                            .expect("deregister socket fail");
+                        continue;
+                    }
+                    last_active = SystemTime::now();
+                    transfer.active = Some(last_active);
+                    while transfer.buffer.len() >= 2 {
+                        let size =
+                            u16::from_be_bytes([transfer.buffer[0], transfer.buffer[1]]) as usize;
+                        if transfer.buffer.len() < size + 2 {
+                            break;
+                        }
+                        let raw: Vec<u8> = transfer.buffer.drain(..size + 2).skip(2).collect();
+                        let started = match transfer.start {
+                            Some(v) => v,
+                            None => {
+                                error!("receive message without transfer");
+                                continue;
+                            }
+                        };
+                        if raw.len() < HEADER_SIZE {
+                            error!("receive incomplete dns message");
+                            continue;
+                        }
+                        let message = match Message::from_vec(&raw) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!("parse dns message error: {}", e);
+                                continue;
+                            }
+                        };
+                        let progress = transfer.receive(&message, raw.len(), &arguments.mode);
+                        // the response code and type are counted from the first message
+                        if transfer.messages == 1 {
+                            let inspection = self.sessions[i].inspect(&raw, &message);
+                            consumer.receive(&MessageOrHeader::Message((message, 0.0)));
+                            consumer.store.update_inspection(&inspection);
+                        }
+                        match &progress {
+                            Progress::Continue => continue,
+                            Progress::Finished => {
+                                let duration = started.elapsed().unwrap().as_secs_f64();
+                                consumer.histogram.add(duration);
+                                consumer.store.transfer.update(
+                                    transfer.records,
+                                    transfer.bytes,
+                                    transfer.messages,
+                                    duration,
+                                );
+                                debug!(
+                                    "transfer finished in socket {} records={} messages={}",
+                                    i, transfer.records, transfer.messages
+                                );
+                            }
+                            // the failed transfer is not counted in transfer and latency
+                            Progress::Failed(reason) => {
+                                debug!("transfer failed in socket {}: {}", i, reason);
+                                consumer.store.transfer.fail(reason);
+                            }
+                        }
+                        receive_counter += 1;
+                        transfer.reset();
+                        if transfer_interval.as_secs() > 0 {
+                            transfer.resume_at = Some(SystemTime::now().add(transfer_interval));
+                        } else {
+                            self.poll
+                                .registry()
+                                .reregister(connection, token, Interest::WRITABLE)
+                                .expect("reregister fail");
+                        }
+                    }
+                }
+            }
+            let now = SystemTime::now();
+            for (i, transfer) in self.transfers.iter_mut().enumerate() {
+                if closed[i] {
+                    continue;
+                }
+                // the connection is closed since the rest of transfer may still arrive
+                let expired = match transfer.active {
+                    Some(active) => now.duration_since(active).unwrap_or_default() > timeout,
+                    None => false,
+                };
+                if expired {
+                    error!("transfer in socket {} timeout", i);
+                    consumer.store.transfer.fail("Timeout");
+                    closed[i] = true;
+                    transfer.reset();
+                    self.poll
+                        .registry()
+                        .deregister(&mut self.sockets[i])
+                        .expect("deregister socket fail");
+                    continue;
+                }
+                if let Some(resume_at) = transfer.resume_at {
+                    if now >= resume_at && !stopped {
+                        transfer.resume_at = None;
+                        self.poll
+                            .registry()
+                            .reregister(&mut self.sockets[i], Token(i), Interest::WRITABLE)
+                            .expect("reregister fail");
+                    }
+                }
+            }
+            let in_progress = self
+                .transfers
+                .iter()
+                .zip(closed.iter())
+                .any(|(t, c)| !c && (t.start.is_some() || (t.resume_at.is_some() && !stopped)));
+            if (max_send > 0 && receive_counter == max_send)
+                || (stopped && !in_progress)
+                || closed.iter().all(|c| *c)
+            {
+                debug!(
+                    "should break loop send = {} receive = {} cpu={}",
+                    send_counter, receive_counter, id
+                );
+                break 'outer;
+            }
+            if let Err(e) = self
+                .poll
+                .poll(&mut self.events, Some(Duration::from_millis(100)))
+            {
+                error!("poll event fail: {}", e);
+                break;
+            }
+            if interval != 0 {
+                let now = SystemTime::now();
+                if now >= next_status_send {
+                    producer
+                        .store
+                        .set_send_duration(now.duration_since(start).unwrap());
+                    producer.update_interval_rate();
+                    consumer.store.set_receive_total(receive_counter);
+                    consumer.update_report();
+                    if let Err(err) = sender.send((producer.store.clone(), consumer.store.clone()))
+                    {
+                        error!("send interval status fail: {:?}", err)
+                    }
+                    next_status_send = now.add(Duration::from_secs(interval));
+                }
+            }
+        }
+        std::mem::drop(sender);
+        producer
+            .store
+            .set_send_duration(last_active.duration_since(start).unwrap());
+        consumer.store.set_receive_total(receive_counter);
+        producer.update_total_rate();
+        consumer.receive(&MessageOrHeader::End);
+        for (i, socket) in self.sockets.iter_mut().enumerate() {
+            if !closed[i] {
+                self.poll
+                    .registry()
+                    .deregister(socket)
+                    .expect("deregister socket fail");
+            }
+        }
+        (producer.store, consumer.store)
+    }
+}
+
+impl TransferWorker {
+    // boxed like the other workers, the runner only holds dyn Worker
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(arguments: Argument) -> Box<dyn Worker> {
+        let server_port = format!("{}:{}", arguments.server, arguments.port);
+        let poll = Poll::new().expect("create async poll fail");
+        let events = Events::with_capacity(1024);
+        let mut sockets = vec![];
+        let mut sessions = vec![];
+        let mut transfers = vec![];
+
+        for i in 0..arguments.client {
+            match TcpStream::connect(
+                server_port
+                    .parse()
+                    .expect("server ip and port can't be connect success"),
+            ) {
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+                Ok(mut stream) => {
+                    poll.registry()
+                        .register(&mut stream, Token(i), Interest::WRITABLE)
+                        .expect("registr event fail");
+                    sockets.push(stream);
+                    sessions.push(Session::new(&arguments));
+                    transfers.push(Transfer::default());
+                }
+            }
+        }
+        Box::new(TransferWorker {
+            arguments,
+            poll,
+            events,
+            sockets,
+            sessions,
+            transfers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::utils::Mode;
+    use crate::workers::transfer::{Progress, Transfer};
+    use std::str::FromStr;
+    use trust_dns_client::op::{Message, ResponseCode};
+    use trust_dns_client::rr::rdata::SOA;
+    use trust_dns_client::rr::{Name, RData, Record};
+
+    fn message(records: &[Option<u32>]) -> Message {
+        let name = Name::from_str("example.com.").unwrap();
+        let mut message = Message::new();
+        for record in records {
+            let rdata = match record {
+                Some(serial) => {
+                    RData::SOA(SOA::new(name.clone(), name.clone(), *serial, 0, 0, 0, 0))
+                }
+                None => RData::A("192.0.2.1".parse().unwrap()),
+            };
+            message.add_answer(Record::from_rdata(name.clone(), 300, rdata));
+        }
+        message
+    }
+
+    #[test]
+    fn test_axfr() {
+        let mut transfer = Transfer::default();
+        assert_eq!(
+            transfer.receive(&message(&[Some(3), None]), 100, &Mode::Axfr),
+            Progress::Continue
+        );
+        assert_eq!(
+            transfer.receive(&message(&[None, None]), 100, &Mode::Axfr),
+            Progress::Continue
+        );
+        assert_eq!(
+            transfer.receive(&message(&[None, Some(3)]), 100, &Mode::Axfr),
+            Progress::Finished
+        );
+        assert_eq!(transfer.records, 6);
+        assert_eq!(transfer.messages, 3);
+        assert_eq!(transfer.bytes, 300);
+
+        // the first record must be SOA
+        let mut transfer = Transfer::default();
+        assert_eq!(
+            transfer.receive(&message(&[None, Some(3)]), 100, &Mode::Axfr),
+            Progress::Failed("Malformed".to_string())
+        );
+        let mut transfer = Transfer::default();
+        assert_eq!(
+            transfer.receive(&message(&[]), 100, &Mode::Axfr),
+            Progress::Failed("Malformed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_refused() {
+        let mut transfer = Transfer::default();
+        let mut refused = message(&[]);
+        refused.set_response_code(ResponseCode::Refused);
+        assert_eq!(
+            transfer.receive(&refused, 100, &Mode::Axfr),
+            Progress::Failed("Refused".to_string())
+        );
+        assert_eq!(transfer.messages, 1);
+        assert_eq!(transfer.records, 0);
+    }
+
+    #[test]
+    fn test_ixfr() {
+        let mut transfer = Transfer::default();
+        assert_eq!(
+            transfer.receive(&message(&[Some(3)]), 100, &Mode::Ixfr),
+            Progress::Finished
+        );
+
+        // incremental transfer from serial 1 to 3
+        let mut transfer = Transfer::default();
+        let diff = [
+            Some(3),
+            Some(1),
+            None,
+            Some(2),
+            None,
+            Some(2),
+            Some(3),
+            None,
+        ];
+        assert_eq!(
+            transfer.receive(&message(&diff), 100, &Mode::Ixfr),
+            Progress::Continue
+        );
+        assert_eq!(
+            transfer.receive(&message(&[Some(3)]), 100, &Mode::Ixfr),
+            Progress::Finished
+        );
+
+        // fallback to full zone transfer
+        let mut transfer = Transfer::default();
+        assert_eq!(
+            transfer.receive(&message(&[Some(3), None]), 100, &Mode::Ixfr),
+            Progress::Continue
+        );
+        assert_eq!(
+            transfer.receive(&message(&[None, Some(3)]), 100, &Mode::Ixfr),
+            Progress::Finished
+        );
+    }
+}
@@ -1,13 +1,16 @@
-use super::{MessageOrHeader, Worker, HEADER_SIZE};
+use super::inflight::InFlight;
+use super::session::Session;
+use super::{MessageOrHeader, Worker, MAX_QUESTION_SIZE};
 use crate::runner::consumer::ResponseConsumer;
 use crate::runner::report::StatusStore;
+use crate::runner::safeguard::Safeguard;
 use crate::runner::{producer::PacketGeneratorStatus, QueryProducer};
 use crate::utils::Argument;
 use mio::net::UdpSocket;
 use mio::{Events, Interest, Poll, Token};
 use std::collections::HashMap;
 use std::ops::Add;
-use trust_dns_client::op::Header;
+use trust_dns_client::op::{Header, Message, ResponseCode};
 use trust_dns_client::proto::serialize::binary::BinDecodable;
 
 pub struct UDPWorker {
@@ -15,6 +18,7 @@ pub struct UDPWorker {
     poll: Poll,
     events: Events,
     sockets: Vec<UdpSocket>,
+    sessions: Vec<Session>,
 }
 
 impl Worker for UDPWorker {
@@ -29,17 +33,29 @@ impl Worker for UDPWorker {
             std::time::SystemTime::now().add(std::time::Duration::from_secs(interval));
         let mut producer = QueryProducer::new(arguments.clone());
         let mut consumer = ResponseConsumer::new();
-        #[allow(unused_assignments)]
-        let mut stop_sender_timer = std::time::SystemTime::now();
+        let mut safeguard = Safeguard::new(&arguments);
+        consumer.set_expectations(producer.expectations().clone());
         let max_send = arguments.max as u64;
+        let mut parse_full_response = producer.parse_full_response();
         let mut send_counter: u64 = 0;
         let mut receive_counter: u64 = 0;
-        let start = std::time::SystemTime::now();
+        let mut start = std::time::SystemTime::now();
+        let mut stop_sender_timer = start;
+        // the last time a query is sent or the pacer holds the next one, a
+        // worker with low rate share waits longer than the idle limit
+        let mut last_active = start;
+        let mut inflight = InFlight::new(
+            self.sockets.len(),
+            arguments.max_inflight,
+            std::time::Duration::from_secs(arguments.timeout as u64),
+        );
 
         if let Err(e) = self.poll.poll(&mut self.events, None) {
             error!("poll event fail: {}", e.to_string());
         };
         let mut time_store = HashMap::new();
+        // the receive buffer is reused by all the sockets
+        let mut buffer = vec![0; u16::MAX as usize];
 
         'outer: loop {
             for event in self.events.iter() {
@@ -47,8 +63,17 @@ impl Worker for UDPWorker {
                 let token = event.token();
                 match token {
                     Token(i) if event.is_writable() => {
+                        if !inflight.available(i) {
+                            // parked until another query is answered
+                            self.poll
+                                .registry()
+                                .reregister(&mut self.sockets[i], token, Interest::READABLE)
+                                .expect("reregister fail");
+                            continue;
+                        }
                         match producer.retrieve() {
                             PacketGeneratorStatus::Success(data, qtype) => {
+                                let data = self.sessions[i].prepare(data);
                                 let key = ((data[0] as u16) << 8) | (data[1] as u16);
                                 if key % 10 == 1 {
                                     time_store.insert(key, std::time::SystemTime::now());
@@ -56,8 +81,16 @@ impl Worker for UDPWorker {
                                 if let Err(e) = self.sockets[i].send(data) {
                                     error!("send error : {}", e);
                                     producer.return_back();
+                                    // the query is not in flight, try again later
+                                    self.poll
+                                        .registry()
+                                        .reregister(&mut self.sockets[i], token, Interest::WRITABLE)
+                                        .expect("reregister fail");
+                                    continue;
                                 }
+                                inflight.sent(i);
                                 stop_sender_timer = std::time::SystemTime::now();
+                                last_active = stop_sender_timer;
                                 self.poll
                                     .registry()
                                     .reregister(&mut self.sockets[i], token, Interest::READABLE)
@@ -72,6 +105,7 @@ impl Worker for UDPWorker {
                             }
                             PacketGeneratorStatus::Wait(_) => {
                                 // sleep(std::time::Duration::from_nanos(wait));
+                                last_active = std::time::SystemTime::now();
                                 self.poll
                                     .registry()
                                     .reregister(&mut self.sockets[i], token, Interest::WRITABLE)
@@ -84,12 +118,42 @@ impl Worker for UDPWorker {
                     }
                     Token(i) if event.is_readable() => {
                         // Read Event
-                        let mut buffer = vec![0; HEADER_SIZE];
-                        if let Ok(size) = self.sockets[i].recv(&mut buffer) {
+                        // the question is read for matching the response
+                        let limit = if parse_full_response {
+                            buffer.len()
+                        } else {
+                            MAX_QUESTION_SIZE
+                        };
+                        if let Ok(size) = self.sockets[i].recv(&mut buffer[..limit]) {
+                            // keep waiting for the answer of outstanding query
+                            if !self.sessions[i].accept(&buffer[..size]) {
+                                debug!("unexpected response in socket {} cpu={}", i, id);
+                                consumer.store.update_unexpected();
+                                self.poll
+                                    .registry()
+                                    .reregister(&mut self.sockets[i], token, Interest::READABLE)
+                                    .expect("reregister fail");
+                                continue;
+                            }
+                            let next = inflight.release(i);
+                            if next != i {
+                                self.poll
+                                    .registry()
+                                    .reregister(&mut self.sockets[i], token, Interest::READABLE)
+                                    .expect("reregister fail");
+                            }
                             self.poll
                                 .registry()
-                                .reregister(&mut self.sockets[i], token, Interest::WRITABLE)
+                                .reregister(
+                                    &mut self.sockets[next],
+                                    Token(next),
+                                    Interest::WRITABLE,
+                                )
                                 .expect("reregister fail");
+                            // the answer of query sent in warmup is not counted
+                            if inflight.take_warmup(i) {
+                                continue;
+                            }
                             debug!(
                                 "receive success in socket {} current={} cpu={}",
                                 i, receive_counter, id
@@ -101,39 +165,96 @@ impl Worker for UDPWorker {
                                     duration = record_start.elapsed().unwrap().as_secs_f64();
                                 }
                             }
-                            if let Ok(message) = Header::from_bytes(&buffer[..size]) {
+                            if parse_full_response {
+                                match Message::from_vec(&buffer[..size]) {
+                                    Ok(message) => {
+                                        let inspection =
+                                            self.sessions[i].inspect(&buffer[..size], &message);
+                                        consumer.receive(&MessageOrHeader::Message((
+                                            message, duration,
+                                        )));
+                                        consumer.store.update_inspection(&inspection);
+                                    }
+                                    Err(e) => error!("parse dns message error: {}", e),
+                                }
+                            } else if let Ok(message) = Header::from_bytes(&buffer[..size]) {
                                 consumer.receive(&MessageOrHeader::Header((message, duration)));
                             } else {
                                 error!("parse dns message error");
                             }
                             receive_counter += 1;
                         }
-                        if (max_send > 0 && (receive_counter == max_send))
-                            || stop_sender_timer.elapsed().unwrap()
-                                > std::time::Duration::from_secs(5)
-                        {
-                            debug!(
-                                "should break loop {} {} cpu={}",
-                                send_counter, receive_counter, id
-                            );
-                            break 'outer;
-                        }
                     }
                     _ => {
                         warn!("Got event for unexpected token: {:?}", event);
                     }
                 }
             }
-            if let Err(e) = self.poll.poll(&mut self.events, None) {
+            // the idle sockets have no event after the producer stopped
+            if (max_send > 0 && (receive_counter == max_send) && !producer.warming_up())
+                || last_active.elapsed().unwrap_or_default() > std::time::Duration::from_secs(5)
+                || (producer.stop_requested() && inflight.count() == 0)
+            {
+                debug!(
+                    "should break loop {} {} cpu={}",
+                    send_counter, receive_counter, id
+                );
+                break 'outer;
+            }
+            if let Err(e) = self.poll.poll(
+                &mut self.events,
+                Option::from(std::time::Duration::from_millis(100)),
+            ) {
                 error!("poll event fail: {}", e.to_string());
                 break;
             }
+            // the status of warmup is dropped and the measurement starts
+            if producer.end_warmup() {
+                consumer.reset();
+                inflight.end_warmup();
+                safeguard.reset();
+                send_counter = 0;
+                receive_counter = 0;
+                start = std::time::SystemTime::now();
+            }
+            if !producer.stopped() {
+                if let Some(reason) = safeguard.check(
+                    std::time::Instant::now(),
+                    send_counter,
+                    receive_counter,
+                    consumer
+                        .store
+                        .reply_code_total(u16::from(ResponseCode::ServFail)),
+                ) {
+                    producer.abort(reason);
+                }
+            }
+            // the run is not over while it is paused by control command
+            if producer.paused() {
+                last_active = std::time::SystemTime::now();
+            }
+            if producer.take_source_change() {
+                consumer.set_expectations(producer.expectations().clone());
+                parse_full_response = producer.parse_full_response();
+            }
+            // give up the queries without response in timeout
+            for (expired, next) in inflight.expire() {
+                self.sessions[expired].expire();
+                if !inflight.take_warmup(expired) {
+                    consumer.store.update_timeout();
+                }
+                self.poll
+                    .registry()
+                    .reregister(&mut self.sockets[next], Token(next), Interest::WRITABLE)
+                    .expect("reregister fail");
+            }
             if interval != 0 {
                 let now = std::time::SystemTime::now();
                 if now >= next_status_send {
                     producer
                         .store
                         .set_send_duration(now.duration_since(start.clone()).unwrap());
+                    producer.update_interval_rate();
                     consumer.store.set_receive_total(receive_counter);
                     consumer.update_report();
                     if let Err(err) = sender.send((producer.store.clone(), consumer.store.clone()))
@@ -158,8 +279,9 @@ impl Worker for UDPWorker {
         }
         producer
             .store
-            .set_send_duration(stop_sender_timer.duration_since(start).unwrap());
+            .set_send_duration(stop_sender_timer.duration_since(start).unwrap_or_default());
         consumer.store.set_receive_total(receive_counter);
+        producer.update_total_rate();
         consumer.receive(&MessageOrHeader::End);
         for socket in self.sockets.iter_mut() {
             self.poll
@@ -178,6 +300,7 @@ impl UDPWorker {
         let poll = Poll::new().expect("create async poll fail");
         let events = Events::with_capacity(1024);
         let mut sockets = vec![];
+        let mut sessions = vec![];
 
         for i in 0..arguments.client {
             let mut socket = UdpSocket::bind(
@@ -199,6 +322,7 @@ impl UDPWorker {
                 .expect("registr event fail");
             debug!("register for socket {}", i);
             sockets.push(socket);
+            sessions.push(Session::new(&arguments));
         }
 
         Box::new(UDPWorker {
@@ -206,6 +330,83 @@ impl UDPWorker {
             poll,
             events,
             sockets,
+            sessions,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::runner::rate::RateProfile;
+    use crate::utils::Argument;
+    use crate::workers::udp::UDPWorker;
+    use std::net::UdpSocket;
+    use std::thread;
+
+    // the server answers each query with the query itself
+    fn echo_server() -> u16 {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buffer = [0; 512];
+            while let Ok((size, peer)) = server.recv_from(&mut buffer) {
+                buffer[2] |= 0x80;
+                let _ = server.send_to(&buffer[..size], peer);
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_low_rate_share() {
+        // the second query is sent 6 seconds after the first one
+        let arguments = Argument {
+            server: "127.0.0.1".to_string(),
+            port: echo_server(),
+            client: 1,
+            max: 2,
+            rate_profile: Some(RateProfile::Constant(1.0 / 6.0)),
+            ..Default::default()
+        };
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let (producer, consumer) = UDPWorker::new(arguments).run(0, sender);
+        assert_eq!(producer.query_total(), 2);
+        assert_eq!(consumer.receive_total(), 2);
+    }
+
+    #[test]
+    fn test_unanswered_timeout() {
+        // the server drops all the queries, most of them are not sampled
+        // for latency but all of them time out
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let arguments = Argument {
+            server: "127.0.0.1".to_string(),
+            port: server.local_addr().unwrap().port(),
+            client: 10,
+            max: 10,
+            timeout: 1,
+            ..Default::default()
+        };
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let (producer, consumer) = UDPWorker::new(arguments).run(0, sender);
+        assert_eq!(producer.query_total(), 10);
+        assert_eq!(consumer.receive_total(), 0);
+        assert_eq!(consumer.timeout_total(), 10);
+    }
+
+    #[test]
+    fn test_no_query_sent() {
+        let arguments = Argument {
+            server: "127.0.0.1".to_string(),
+            port: echo_server(),
+            client: 1,
+            until_stop: 1,
+            rate_profile: Some(RateProfile::Constant(0.0)),
+            ..Default::default()
+        };
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let (producer, consumer) = UDPWorker::new(arguments).run(0, sender);
+        assert_eq!(producer.query_total(), 0);
+        assert_eq!(consumer.receive_total(), 0);
+    }
+}